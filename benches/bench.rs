@@ -1,11 +1,11 @@
-use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
 use pprof::criterion::{Output, PProfProfiler};
 use rand::{
     distributions::{Distribution, Uniform},
     rngs::SmallRng,
     {Rng, SeedableRng},
 };
-use rust_hnsw::{distances::euclidean, hnsw::HNSW};
+use rust_hnsw::{distances::euclidean, dynamic::DynamicHNSW, hnsw::HNSW};
 use std::time::Duration;
 
 const SEED: u64 = 1234;
@@ -74,7 +74,7 @@ fn benchmark_low_d_insertion(c: &mut Criterion) {
     for size in [1, 100] {
         group.bench_function(format!("{size}"), |b| {
             let rng = SmallRng::seed_from_u64(SEED);
-            let mut index = HNSW::new(16, 100, euclidean, rng);
+            let mut index = HNSW::new(16, 100, euclidean, rng).unwrap();
 
             let mut rng_data = SmallRng::seed_from_u64(SEED);
             let data_distribution = Uniform::new(-1.0, 1.0);
@@ -86,7 +86,9 @@ fn benchmark_low_d_insertion(c: &mut Criterion) {
                         .collect()
                 },
                 |vectors: Vec<[_; LOWD]>| {
-                    vectors.iter().for_each(|&v| index.insert(black_box(&v)));
+                    vectors.iter().for_each(|&v| {
+                        index.insert(black_box(&v)).unwrap();
+                    });
                 },
                 BatchSize::SmallInput,
             );
@@ -98,13 +100,13 @@ fn benchmark_low_d_insertion(c: &mut Criterion) {
 fn benchmark_low_d_search(c: &mut Criterion) {
     c.bench_function("low-d search", |b| {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(16, 100, euclidean, rng);
+        let mut index = HNSW::new(16, 100, euclidean, rng).unwrap();
 
         let mut rng_data = SmallRng::seed_from_u64(SEED);
         let data_distribution = Uniform::new(-1.0, 1.0);
         for _ in 0..100 {
             let vector: [_; LOWD] = sample_vector(data_distribution, &mut rng_data);
-            index.insert(&vector);
+            index.insert(&vector).unwrap();
         }
 
         b.iter_batched(
@@ -123,7 +125,7 @@ fn benchmark_high_d_insertion(c: &mut Criterion) {
     for size in [1, 100] {
         group.bench_function(format!("{size}"), |b| {
             let rng = SmallRng::seed_from_u64(SEED);
-            let mut index = HNSW::new(16, 100, euclidean, rng);
+            let mut index = HNSW::new(16, 100, euclidean, rng).unwrap();
 
             let mut rng_data = SmallRng::seed_from_u64(SEED);
             let data_distribution = Uniform::new(-1.0, 1.0);
@@ -135,7 +137,9 @@ fn benchmark_high_d_insertion(c: &mut Criterion) {
                         .collect()
                 },
                 |vectors: Vec<[_; HIGHD]>| {
-                    vectors.iter().for_each(|&v| index.insert(black_box(&v)));
+                    vectors.iter().for_each(|&v| {
+                        index.insert(black_box(&v)).unwrap();
+                    });
                 },
                 BatchSize::SmallInput,
             );
@@ -147,13 +151,13 @@ fn benchmark_high_d_insertion(c: &mut Criterion) {
 fn benchmark_high_d_search(c: &mut Criterion) {
     c.bench_function("high-d search", |b| {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(16, 100, euclidean, rng);
+        let mut index = HNSW::new(16, 100, euclidean, rng).unwrap();
 
         let mut rng_data = SmallRng::seed_from_u64(SEED);
         let data_distribution = Uniform::new(-1.0, 1.0);
         for _ in 0..100 {
             let vector: [_; HIGHD] = sample_vector(data_distribution, &mut rng_data);
-            index.insert(&vector);
+            index.insert(&vector).unwrap();
         }
 
         b.iter_batched(
@@ -166,6 +170,59 @@ fn benchmark_high_d_search(c: &mut Criterion) {
     });
 }
 
+fn benchmark_high_d_search_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hnsw high-d search throughput");
+
+    for size in [1_000, 5_000] {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(16, 100, euclidean, rng).unwrap();
+
+        let mut rng_data = SmallRng::seed_from_u64(SEED);
+        let data_distribution = Uniform::new(-1.0, 1.0);
+        for _ in 0..size {
+            let vector: [_; HIGHD] = sample_vector(data_distribution, &mut rng_data);
+            index.insert(&vector).unwrap();
+        }
+
+        let queries: Vec<[_; HIGHD]> = (0..100)
+            .map(|_| sample_vector(data_distribution, &mut rng_data))
+            .collect();
+
+        // throughput in queries per second, every vector being read through the storage of the index
+        group.throughput(Throughput::Elements(queries.len() as u64));
+        group.bench_function(format!("{size}"), |b| {
+            b.iter(|| {
+                for query in &queries {
+                    let _ = index.search(black_box(query), black_box(10));
+                }
+            });
+        });
+    }
+    group.finish();
+}
+
+fn benchmark_high_d_dynamic_search(c: &mut Criterion) {
+    c.bench_function("high-d dynamic search", |b| {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = DynamicHNSW::new(HIGHD, 16, 100, euclidean, rng).unwrap();
+
+        let mut rng_data = SmallRng::seed_from_u64(SEED);
+        let data_distribution = Uniform::new(-1.0, 1.0);
+        for _ in 0..100 {
+            let vector: [_; HIGHD] = sample_vector(data_distribution, &mut rng_data);
+            index.insert(&vector).unwrap();
+        }
+
+        b.iter_batched(
+            || sample_vector::<HIGHD, _>(data_distribution, &mut rng_data),
+            |query| {
+                let _ = index.search(black_box(&query), black_box(3));
+            },
+            BatchSize::SmallInput,
+        );
+    });
+}
+
 criterion_group!(
     name = benches;
     config = get_config();
@@ -175,5 +232,7 @@ criterion_group!(
     benchmark_low_d_search,
     benchmark_high_d_insertion,
     benchmark_high_d_search,
+    benchmark_high_d_search_throughput,
+    benchmark_high_d_dynamic_search,
 );
 criterion_main!(benches);
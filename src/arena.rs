@@ -0,0 +1,193 @@
+use std::{collections::TryReserveError, fmt, ops::Index};
+
+/// Vector stored in its own cache lines, so that reading it never loads the end of another vector and its elements
+/// can be loaded in aligned SIMD registers
+#[derive(Clone, Copy, PartialEq)]
+#[repr(C, align(64))]
+struct Row<T, const D: usize>([T; D]);
+
+/// Contiguous storage of the vectors of an index, the vector of id `i` being stored in the row `i`. Reading a vector is
+/// a bounds check instead of a hash lookup, and vectors inserted one after another are next to each other in memory.
+/// The rows of removed vectors are left vacant until the ids are renumbered
+#[derive(Clone)]
+pub(crate) struct Vectors<T, const D: usize> {
+    rows: Vec<Row<T, D>>,
+    occupied: Vec<bool>,
+    len: usize,
+}
+
+impl<T: Copy, const D: usize> Vectors<T, D> {
+    pub fn new() -> Self {
+        Self {
+            rows: Vec::new(),
+            occupied: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Return the number of vectors stored
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.occupied.get(id).is_some_and(|&occupied| occupied)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&[T; D]> {
+        self.contains(id).then(|| &self.rows[id].0)
+    }
+
+    /// Store a vector in the row of an id, and return the vector it replaces if any. Vacant rows before it are filled
+    /// with copies of the vector, as elements have no default value
+    pub fn insert(&mut self, id: usize, vector: [T; D]) -> Option<[T; D]> {
+        if id >= self.rows.len() {
+            self.rows.resize(id + 1, Row(vector));
+            self.occupied.resize(id + 1, false);
+        }
+
+        let previous = self.get(id).copied();
+        self.rows[id] = Row(vector);
+        if !self.occupied[id] {
+            self.occupied[id] = true;
+            self.len += 1;
+        }
+
+        previous
+    }
+
+    /// Remove the vector stored with an id and return it. Only the rows left vacant at the end of the storage are
+    /// released, the row of a smaller id stays allocated until the ids are renumbered
+    pub fn remove(&mut self, id: usize) -> Option<[T; D]> {
+        let vector = self.get(id).copied()?;
+        self.occupied[id] = false;
+        self.len -= 1;
+
+        while self.occupied.last() == Some(&false) {
+            self.occupied.pop();
+            self.rows.pop();
+        }
+
+        Some(vector)
+    }
+
+    /// Allocate the rows of the ids below `len`, returning an error instead of aborting when the memory cannot be
+    /// allocated
+    pub fn try_reserve(&mut self, len: usize) -> Result<(), TryReserveError> {
+        self.rows.try_reserve(len.saturating_sub(self.rows.len()))?;
+        self.occupied
+            .try_reserve(len.saturating_sub(self.occupied.len()))
+    }
+
+    /// Release the memory allocated beyond the last occupied row
+    pub fn shrink_to_fit(&mut self) {
+        self.rows.shrink_to_fit();
+        self.occupied.shrink_to_fit();
+    }
+
+    /// Return the ids of the vectors stored, in increasing order
+    pub fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.occupied
+            .iter()
+            .enumerate()
+            .filter_map(|(id, &occupied)| occupied.then_some(id))
+    }
+
+    /// Return the vectors stored along with their id, in increasing order of id
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &[T; D])> + '_ {
+        self.ids().map(|id| (id, &self.rows[id].0))
+    }
+}
+
+impl<T: Copy, const D: usize> Index<usize> for Vectors<T, D> {
+    type Output = [T; D];
+
+    fn index(&self, id: usize) -> &Self::Output {
+        self.get(id).expect("no vector stored with this id")
+    }
+}
+
+impl<T: Copy, const D: usize> FromIterator<(usize, [T; D])> for Vectors<T, D> {
+    fn from_iter<I: IntoIterator<Item = (usize, [T; D])>>(iter: I) -> Self {
+        let mut vectors = Self::new();
+        for (id, vector) in iter {
+            vectors.insert(id, vector);
+        }
+
+        vectors
+    }
+}
+
+/// Two storages are equal if they store the same vectors with the same ids, whatever their vacant rows
+impl<T: Copy + PartialEq, const D: usize> PartialEq for Vectors<T, D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Copy + fmt::Debug, const D: usize> fmt::Debug for Vectors<T, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vectors;
+    use std::mem;
+
+    #[test]
+    fn test_vectors() {
+        let mut vectors = Vectors::new();
+        assert_eq!(vectors.insert(0, [1.0, 2.0]), None);
+        assert_eq!(vectors.insert(3, [3.0, 4.0]), None);
+        assert_eq!(vectors.len(), 2);
+
+        // rows skipped by an id are vacant
+        assert!(!vectors.contains(1));
+        assert_eq!(vectors.get(2), None);
+        assert_eq!(vectors.ids().collect::<Vec<_>>(), [0, 3]);
+
+        assert_eq!(vectors.insert(0, [5.0, 6.0]), Some([1.0, 2.0]));
+        assert_eq!(vectors[0], [5.0, 6.0]);
+        assert_eq!(vectors.len(), 2);
+
+        assert_eq!(vectors.remove(3), Some([3.0, 4.0]));
+        assert_eq!(vectors.remove(3), None);
+        assert_eq!(vectors.len(), 1);
+        assert_eq!(vectors.rows.len(), 1);
+
+        let other = [(0, [5.0, 6.0])].into_iter().collect::<Vectors<f64, 2>>();
+        assert_eq!(vectors, other);
+    }
+
+    #[test]
+    fn test_remove_vacant_rows() {
+        let mut vectors = (0..4)
+            .map(|id| (id, [id as f64; 2]))
+            .collect::<Vectors<f64, 2>>();
+
+        // the row of a removed vector stays allocated until the rows after it are vacant too
+        vectors.remove(1);
+        assert_eq!(vectors.rows.len(), 4);
+        vectors.remove(3);
+        assert_eq!(vectors.rows.len(), 3);
+        vectors.remove(2);
+        assert_eq!(vectors.rows.len(), 1);
+
+        vectors.shrink_to_fit();
+        assert_eq!(vectors.ids().collect::<Vec<_>>(), [0]);
+    }
+
+    #[test]
+    fn test_rows_aligned() {
+        let vectors = [(0, [1.0f32; 3]), (1, [2.0; 3])]
+            .into_iter()
+            .collect::<Vectors<f32, 3>>();
+
+        for id in 0..2 {
+            assert_eq!(vectors[id].as_ptr() as usize % 64, 0);
+        }
+        assert_eq!(mem::size_of_val(&vectors.rows[0]), 64);
+    }
+}
@@ -0,0 +1,417 @@
+use crate::{
+    concurrent::ConcurrentHNSW,
+    dynamic::DynamicHNSW,
+    error::HnswError,
+    hnsw::{NeighborSelection, HNSW},
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+/// Largest level multiplier accepted, for which a node is sampled in at most a few hundred levels. The default one,
+/// `1 / ln(M)`, is below 1.5 for any `M` of at least 2
+const MAX_LEVEL_MULTIPLIER: f64 = 10.0;
+
+/// Return an error if the size of the dynamic candidate list of a search is 0, as a search would explore nothing
+pub(crate) fn check_ef_search(ef_search: usize) -> Result<usize, HnswError> {
+    if ef_search == 0 {
+        return Err(HnswError::InvalidParameter {
+            name: "ef_search",
+            reason: "must be at least 1",
+        });
+    }
+
+    Ok(ef_search)
+}
+
+/// Parameters of the index once validated, with the defaults derived from `M` resolved
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(crate) struct Parameters {
+    pub connections: usize,
+    pub max_connections: usize,
+    pub max_connections_0: usize,
+    pub level_multiplier: f64,
+    pub ef_construction: usize,
+    pub ef_search: usize,
+    pub neighbor_selection: NeighborSelection,
+    pub pruning: NeighborSelection,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub compact_ids: bool,
+}
+
+impl Parameters {
+    /// Check parameters that were not resolved by a builder, as when they are read from a file
+    pub(crate) fn validate(&self) -> Result<Self, HnswError> {
+        HnswBuilder::new(self.connections)
+            .max_connections(self.max_connections)
+            .max_connections_0(self.max_connections_0)
+            .level_multiplier(self.level_multiplier)
+            .ef_construction(self.ef_construction)
+            .ef_search(self.ef_search)
+            .neighbor_selection(self.neighbor_selection)
+            .pruning(self.pruning)
+            .compact_ids(self.compact_ids)
+            .parameters()
+    }
+}
+
+/// Builder validating the construction parameters of the index. Parameters not set explicitly follow the
+/// recommendations of the paper: `Mmax = 1.5 * M`, `Mmax0 = 2 * M`, `m_L = 1 / ln(M)` and `ef_search = ef_construction`.
+/// With the `serde` feature, it can be read from a configuration in which parameters left out take their default value
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct HnswBuilder {
+    connections: usize,
+    max_connections: Option<usize>,
+    max_connections_0: Option<usize>,
+    level_multiplier: Option<f64>,
+    ef_construction: usize,
+    ef_search: Option<usize>,
+    neighbor_selection: NeighborSelection,
+    pruning: NeighborSelection,
+    compact_ids: bool,
+    seed: Option<u64>,
+}
+
+impl Default for HnswBuilder {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+impl HnswBuilder {
+    /// Create a builder for an index connecting each inserted vector to `connections` (M) neighbors
+    pub fn new(connections: usize) -> Self {
+        Self {
+            connections,
+            max_connections: None,
+            max_connections_0: None,
+            level_multiplier: None,
+            ef_construction: 100,
+            ef_search: None,
+            neighbor_selection: NeighborSelection::default(),
+            pruning: NeighborSelection::default(),
+            compact_ids: false,
+            seed: None,
+        }
+    }
+
+    /// Set the maximum number of connections of a node in the levels above the base one (Mmax)
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Set the maximum number of connections of a node in the base level (Mmax0)
+    pub fn max_connections_0(mut self, max_connections_0: usize) -> Self {
+        self.max_connections_0 = Some(max_connections_0);
+        self
+    }
+
+    /// Set the normalization factor of the level distribution (m_L)
+    pub fn level_multiplier(mut self, level_multiplier: f64) -> Self {
+        self.level_multiplier = Some(level_multiplier);
+        self
+    }
+
+    /// Set the size of the dynamic candidate list used when inserting a vector
+    pub fn ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction;
+        self
+    }
+
+    /// Set the size of the dynamic candidate list used when searching the base level
+    pub fn ef_search(mut self, ef_search: usize) -> Self {
+        self.ef_search = Some(ef_search);
+        self
+    }
+
+    /// Set the strategy used to select the neighbors of a node being inserted
+    pub fn neighbor_selection(mut self, neighbor_selection: NeighborSelection) -> Self {
+        self.neighbor_selection = neighbor_selection;
+        self
+    }
+
+    /// Set the strategy used to select the edges to keep when a node has too many connections. Use
+    /// `NeighborSelection::Simple` to only keep the closest ones
+    pub fn pruning(mut self, pruning: NeighborSelection) -> Self {
+        self.pruning = pruning;
+        self
+    }
+
+    /// Store the ids in the edges of the levels on 32 bits instead of `usize`, which roughly halves the memory taken by
+    /// the graph but limits the index to ids below `u32::MAX`
+    pub fn compact_ids(mut self, compact_ids: bool) -> Self {
+        self.compact_ids = compact_ids;
+        self
+    }
+
+    /// Set the seed of the random number generator used by `build` to sample levels
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Check the parameters and resolve the ones left to their default value
+    pub(crate) fn parameters(&self) -> Result<Parameters, HnswError> {
+        let invalid = |name, reason| Err(HnswError::InvalidParameter { name, reason });
+
+        let connections = self.connections;
+        let max_connections = self
+            .max_connections
+            .unwrap_or((1.5 * (connections as f32)).round() as usize);
+        let max_connections_0 = self.max_connections_0.unwrap_or(2 * connections);
+        let level_multiplier = self
+            .level_multiplier
+            .unwrap_or(1.0 / (connections as f64).ln());
+        let ef_construction = self.ef_construction;
+        let ef_search = self.ef_search.unwrap_or(ef_construction);
+
+        if connections == 0 {
+            return invalid("connections", "must be at least 1");
+        }
+        if max_connections < connections {
+            return invalid("max_connections", "must be at least `connections`");
+        }
+        if max_connections_0 < connections {
+            return invalid("max_connections_0", "must be at least `connections`");
+        }
+        if !level_multiplier.is_finite() || level_multiplier <= 0.0 {
+            return invalid(
+                "level_multiplier",
+                "must be finite and positive, it has to be set explicitly when `connections` is 1",
+            );
+        }
+        if level_multiplier > MAX_LEVEL_MULTIPLIER {
+            return invalid("level_multiplier", "must be at most 10");
+        }
+        if ef_construction < connections {
+            return invalid("ef_construction", "must be at least `connections`");
+        }
+        check_ef_search(ef_search)?;
+
+        Ok(Parameters {
+            connections,
+            max_connections,
+            max_connections_0,
+            level_multiplier,
+            ef_construction,
+            ef_search,
+            neighbor_selection: self.neighbor_selection,
+            pruning: self.pruning,
+            compact_ids: self.compact_ids,
+        })
+    }
+
+    /// Build an empty index using a small random number generator, seeded if a seed was set or from the system entropy
+    /// otherwise
+    pub fn build<T, const D: usize, F>(
+        &self,
+        distance_metric: F,
+    ) -> Result<HNSW<T, D, F, SmallRng>, HnswError>
+    where
+        T: Sized + Copy + Debug,
+        F: Fn(&[T], &[T]) -> f64,
+    {
+        self.build_with_rng(distance_metric, self.rng())
+    }
+
+    /// Build an empty index using the given random number generator, the seed is ignored
+    pub fn build_with_rng<T, const D: usize, F, R>(
+        &self,
+        distance_metric: F,
+        rng: R,
+    ) -> Result<HNSW<T, D, F, R>, HnswError>
+    where
+        T: Sized + Copy + Debug,
+        F: Fn(&[T], &[T]) -> f64,
+        R: Rng,
+    {
+        Ok(HNSW::from_parameters(
+            self.parameters()?,
+            distance_metric,
+            rng,
+        ))
+    }
+
+    /// Build an empty index into which vectors can be inserted from several threads, using a small random number
+    /// generator seeded as in `build`
+    pub fn build_concurrent<T, const D: usize, F>(
+        &self,
+        distance_metric: F,
+    ) -> Result<ConcurrentHNSW<T, D, F, SmallRng>, HnswError>
+    where
+        T: Sized + Copy + Debug,
+        F: Fn(&[T], &[T]) -> f64,
+    {
+        self.build_concurrent_with_rng(distance_metric, self.rng())
+    }
+
+    /// Build an empty index into which vectors can be inserted from several threads, using the given random number
+    /// generator, the seed is ignored
+    pub fn build_concurrent_with_rng<T, const D: usize, F, R>(
+        &self,
+        distance_metric: F,
+        rng: R,
+    ) -> Result<ConcurrentHNSW<T, D, F, R>, HnswError>
+    where
+        T: Sized + Copy + Debug,
+        F: Fn(&[T], &[T]) -> f64,
+        R: Rng,
+    {
+        Ok(ConcurrentHNSW::from_parameters(
+            self.parameters()?,
+            distance_metric,
+            rng,
+        ))
+    }
+
+    /// Build an empty index of vectors whose dimension is given at runtime, using a small random number generator
+    /// seeded as in `build`
+    pub fn build_dynamic<T, F>(
+        &self,
+        dimension: usize,
+        distance_metric: F,
+    ) -> Result<DynamicHNSW<T, F, SmallRng>, HnswError>
+    where
+        T: Sized + Copy + Debug,
+        F: Fn(&[T], &[T]) -> f64,
+    {
+        self.build_dynamic_with_rng(dimension, distance_metric, self.rng())
+    }
+
+    /// Build an empty index of vectors whose dimension is given at runtime, using the given random number generator,
+    /// the seed is ignored
+    pub fn build_dynamic_with_rng<T, F, R>(
+        &self,
+        dimension: usize,
+        distance_metric: F,
+        rng: R,
+    ) -> Result<DynamicHNSW<T, F, R>, HnswError>
+    where
+        T: Sized + Copy + Debug,
+        F: Fn(&[T], &[T]) -> f64,
+        R: Rng,
+    {
+        if dimension == 0 {
+            return Err(HnswError::InvalidParameter {
+                name: "dimension",
+                reason: "must be at least 1",
+            });
+        }
+
+        Ok(DynamicHNSW::from_parameters(
+            self.parameters()?,
+            dimension,
+            distance_metric,
+            rng,
+        ))
+    }
+
+    /// Create a small random number generator, seeded if a seed was set or from the system entropy otherwise
+    fn rng(&self) -> SmallRng {
+        match self.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_entropy(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HnswBuilder;
+    use crate::{distances::euclidean, error::HnswError, hnsw::HNSW};
+    use rand::rngs::SmallRng;
+
+    fn invalid_parameter(builder: HnswBuilder) -> Option<&'static str> {
+        match builder.parameters() {
+            Err(HnswError::InvalidParameter { name, .. }) => Some(name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let parameters = HnswBuilder::new(16).parameters().unwrap();
+
+        assert_eq!(parameters.max_connections, 24);
+        assert_eq!(parameters.max_connections_0, 32);
+        assert!((parameters.level_multiplier - 1.0 / 16f64.ln()).abs() < f64::EPSILON);
+        assert_eq!(parameters.ef_construction, 100);
+        assert_eq!(parameters.ef_search, 100);
+    }
+
+    #[test]
+    fn test_explicit_parameters() {
+        let parameters = HnswBuilder::new(1)
+            .max_connections(4)
+            .max_connections_0(8)
+            .level_multiplier(0.5)
+            .ef_construction(10)
+            .ef_search(20)
+            .parameters()
+            .unwrap();
+
+        assert_eq!(parameters.connections, 1);
+        assert_eq!(parameters.max_connections, 4);
+        assert_eq!(parameters.max_connections_0, 8);
+        assert_eq!(parameters.level_multiplier, 0.5);
+        assert_eq!(parameters.ef_construction, 10);
+        assert_eq!(parameters.ef_search, 20);
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        assert_eq!(invalid_parameter(HnswBuilder::new(0)), Some("connections"));
+        assert_eq!(
+            invalid_parameter(HnswBuilder::new(1)),
+            Some("level_multiplier")
+        );
+        assert_eq!(
+            invalid_parameter(HnswBuilder::new(8).level_multiplier(f64::NAN)),
+            Some("level_multiplier")
+        );
+        assert_eq!(
+            invalid_parameter(HnswBuilder::new(8).level_multiplier(1e9)),
+            Some("level_multiplier")
+        );
+        assert_eq!(
+            invalid_parameter(HnswBuilder::new(8).max_connections(4)),
+            Some("max_connections")
+        );
+        assert_eq!(
+            invalid_parameter(HnswBuilder::new(8).max_connections_0(4)),
+            Some("max_connections_0")
+        );
+        assert_eq!(
+            invalid_parameter(HnswBuilder::new(8).ef_construction(4)),
+            Some("ef_construction")
+        );
+        assert_eq!(
+            invalid_parameter(HnswBuilder::new(8).ef_search(0)),
+            Some("ef_search")
+        );
+    }
+
+    #[test]
+    fn test_build_seeded() {
+        let builder = HnswBuilder::new(4).ef_construction(16).seed(1234);
+        let mut index1: HNSW<f64, 2, _, SmallRng> = builder.build(euclidean).unwrap();
+        let mut index2: HNSW<f64, 2, _, SmallRng> = builder.build(euclidean).unwrap();
+
+        for i in 0..100 {
+            index1.insert(&[i as f64; 2]).unwrap();
+            index2.insert(&[i as f64; 2]).unwrap();
+        }
+
+        // same seed, same level assignments
+        assert_eq!(index1.num_levels(), index2.num_levels());
+        assert!(index1
+            .levels
+            .iter()
+            .zip(&index2.levels)
+            .all(|(level1, level2)| level1.ids().count() == level2.ids().count()));
+    }
+}
@@ -0,0 +1,745 @@
+use crate::{
+    builder::{self, HnswBuilder, Parameters},
+    error::HnswError,
+    graph::{self, Candidate, Graph},
+    hnsw::{NeighborSelection, SearchResult, HNSW},
+    level,
+};
+use rand::Rng;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+const NUM_SEGMENTS: usize = usize::BITS as usize;
+
+/// Vector of a node and its edges in each level it belongs to, each of them behind its own lock
+struct Node<T, const D: usize> {
+    vector: [T; D],
+    levels: Box<[RwLock<Vec<usize>>]>,
+}
+
+/// Append-only storage of nodes indexed by their id, which can be read and extended concurrently without locking. Nodes
+/// are stored in segments of doubling size, allocated on first use and never moved afterwards
+struct Nodes<N> {
+    segments: [OnceLock<Box<[OnceLock<N>]>>; NUM_SEGMENTS],
+}
+
+impl<N> Nodes<N> {
+    fn new() -> Self {
+        Self {
+            segments: std::array::from_fn(|_| OnceLock::new()),
+        }
+    }
+
+    /// Return the segment storing an id and its offset in it, segment `i` storing the ids from `2^i - 1` to `2^(i+1) - 2`
+    fn locate(id: usize) -> (usize, usize) {
+        let position = id + 1;
+        let segment_index = position.ilog2() as usize;
+
+        (segment_index, position - (1 << segment_index))
+    }
+
+    fn get(&self, id: usize) -> Option<&N> {
+        let (segment_index, offset) = Self::locate(id);
+
+        self.segments[segment_index].get()?[offset].get()
+    }
+
+    /// Store the node of a newly allocated id
+    fn set(&self, id: usize, node: N) {
+        let (segment_index, offset) = Self::locate(id);
+        let segment = self.segments[segment_index]
+            .get_or_init(|| (0..1 << segment_index).map(|_| OnceLock::new()).collect());
+
+        // ids are allocated once so the slot is always empty
+        let _ = segment[offset].set(node);
+    }
+
+    /// Consume the storage and return each node with its id
+    fn into_nodes(self) -> impl Iterator<Item = (usize, N)> {
+        self.segments
+            .into_iter()
+            .enumerate()
+            .filter_map(|(segment_index, segment)| Some((segment_index, segment.into_inner()?)))
+            .flat_map(|(segment_index, segment)| {
+                segment
+                    .into_vec()
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(move |(offset, node)| {
+                        Some(((1 << segment_index) - 1 + offset, node.into_inner()?))
+                    })
+            })
+    }
+}
+
+/// Node where every traversal starts, along with the index of the top level it belongs to
+#[derive(Debug, Clone, Copy)]
+struct EntryPoint {
+    id: usize,
+    level_index: usize,
+}
+
+// a panic while holding a lock cannot leave a list of edges half written, so poisoned locks are used as is
+fn read<L>(lock: &RwLock<L>) -> RwLockReadGuard<'_, L> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<L>(lock: &RwLock<L>) -> RwLockWriteGuard<'_, L> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Index into which vectors can be inserted from several threads at the same time, to build large indexes faster or to
+/// serve searches while new vectors stream in. The edges of each node in each level are behind their own lock, so that
+/// insertions only wait for each other when they connect to the same nodes, and searches always read a complete list
+/// of edges, either before or after an insertion modified it. Once built, it can be turned into an `HNSW` with
+/// `into_index`
+pub struct ConcurrentHNSW<T, const D: usize, F, R> {
+    parameters: Parameters,
+    distance_metric: F,
+    rng: Mutex<R>,
+    nodes: Nodes<Node<T, D>>,
+    entry_point: RwLock<Option<EntryPoint>>,
+    next_id: AtomicUsize,
+}
+
+impl<T, const D: usize, F, R> ConcurrentHNSW<T, D, F, R>
+where
+    T: Sized + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    /// Create an empty index using the parameters recommended in the paper for the given `M` and `ef_construction`.
+    /// Use `HnswBuilder::build_concurrent` to set the other parameters
+    pub fn new(
+        connections: usize,
+        ef_construction: usize,
+        distance_metric: F,
+        rng: R,
+    ) -> Result<Self, HnswError> {
+        HnswBuilder::new(connections)
+            .ef_construction(ef_construction)
+            .build_concurrent_with_rng(distance_metric, rng)
+    }
+
+    pub(crate) fn from_parameters(parameters: Parameters, distance_metric: F, rng: R) -> Self {
+        Self {
+            parameters,
+            distance_metric,
+            rng: Mutex::new(rng),
+            nodes: Nodes::new(),
+            entry_point: RwLock::new(None),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// Define the highest level by sampling from an exponentially decaying distribution
+    fn sample_max_level_index(&self) -> usize {
+        let mut rng = self.rng.lock().unwrap_or_else(PoisonError::into_inner);
+
+        graph::sample_level_index(&mut *rng, self.parameters.level_multiplier)
+    }
+
+    fn get_node(&self, id: usize) -> Result<&Node<T, D>, HnswError> {
+        self.nodes.get(id).ok_or(HnswError::NodeNotFound(id))
+    }
+
+    /// Add an edge from a node to another one in a level, then shrink its edges if it has more than the maximum number
+    /// of connections allowed in the level. The edges of the node stay locked for the whole operation
+    fn connect(
+        &self,
+        level_index: usize,
+        node_id: usize,
+        neighbor_id: usize,
+    ) -> Result<(), HnswError> {
+        let node = self.get_node(node_id)?;
+        let mut edges = write(&node.levels[level_index]);
+
+        if edges.contains(&neighbor_id) {
+            return Ok(());
+        }
+        edges.push(neighbor_id);
+
+        let max_connections = self.max_connections(level_index);
+        if edges.len() > max_connections {
+            let mut candidates = edges
+                .iter()
+                .map(|&id| {
+                    let distance = self.distance(&node.vector, self.vector(id)?)?;
+                    Ok(Candidate::new(id, distance))
+                })
+                .collect::<Result<Vec<_>, HnswError>>()?;
+            candidates.sort_unstable();
+
+            // extending the candidates would lock the edges of other nodes while holding this lock, which can deadlock
+            let pruning = match self.parameters.pruning {
+                NeighborSelection::Heuristic {
+                    keep_pruned_connections,
+                    ..
+                } => NeighborSelection::Heuristic {
+                    extend_candidates: false,
+                    keep_pruned_connections,
+                },
+                NeighborSelection::Simple => NeighborSelection::Simple,
+            };
+            let kept = graph::select_neighbors(
+                self,
+                level_index,
+                node_id,
+                candidates,
+                max_connections,
+                pruning,
+            )?;
+
+            edges.clear();
+            edges.extend(kept.iter().map(|c| c.id));
+        }
+
+        Ok(())
+    }
+
+    /// Connect a node to its nearest neighbors in the levels from `top_level_index` down to `bottom_level_index`,
+    /// traveling the levels above them from an entry point
+    fn connect_levels(
+        &self,
+        node_id: usize,
+        vector: &[T; D],
+        entry_point: EntryPoint,
+        bottom_level_index: usize,
+        top_level_index: usize,
+    ) -> Result<(), HnswError> {
+        // travel hierarchy for levels above the highest level to connect
+        let mut entry_ids = graph::descend(
+            self,
+            vector,
+            entry_point.id,
+            entry_point.level_index,
+            top_level_index,
+        )?;
+
+        // travel hierarchy for levels to connect. Other insertions can already connect to the node through the levels
+        // above, so it is left out of its own candidates
+        for level_index in (bottom_level_index..=top_level_index).rev() {
+            let candidates = graph::search_level(
+                self,
+                level_index,
+                vector,
+                &entry_ids,
+                self.parameters.ef_construction,
+                |id| id != node_id,
+            )?;
+
+            entry_ids = candidates.iter().map(|candidate| candidate.id).collect();
+
+            let neighbors = graph::select_neighbors(
+                self,
+                level_index,
+                node_id,
+                candidates,
+                self.parameters.connections,
+                self.parameters.neighbor_selection,
+            )?;
+
+            for &Candidate { id, .. } in &neighbors {
+                self.connect(level_index, node_id, id)?;
+                self.connect(level_index, id, node_id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Set the size of the dynamic candidate list used when searching the base level, which must be at least 1
+    pub fn with_ef_search(mut self, ef_search: usize) -> Result<Self, HnswError> {
+        self.parameters.ef_search = builder::check_ef_search(ef_search)?;
+        Ok(self)
+    }
+
+    /// Return the number of vectors stored in the index, including the ones being inserted
+    pub fn len(&self) -> usize {
+        self.next_id.load(Ordering::Acquire)
+    }
+
+    /// Return the vector stored with the given id, if any
+    pub fn get(&self, id: usize) -> Option<&[T; D]> {
+        self.nodes.get(id).map(|node| &node.vector)
+    }
+
+    /// Insert a new vector in the index and return the id assigned to it. It can be called from several threads at the
+    /// same time. Vectors for which the distance metric returns NaN, such as a zero vector with the cosine distance, are
+    /// rejected
+    pub fn insert(&self, vector: &[T; D]) -> Result<usize, HnswError> {
+        self.distance(vector, vector)?;
+
+        // as for the other indexes, a node is sampled at most one level above the top level, which also bounds the
+        // number of locks allocated for it
+        let top_level_index = read(&self.entry_point).map(|entry_point| entry_point.level_index);
+        let max_level_index = self
+            .sample_max_level_index()
+            .min(top_level_index.map_or(0, |top_level_index| top_level_index + 1));
+        let levels = (0..=max_level_index)
+            .map(|level_index| RwLock::new(Vec::with_capacity(self.max_connections(level_index))))
+            .collect();
+
+        // the node is stored before being connected, so that it can be read by any thread reaching it through an edge
+        let max_id = level::max_id(self.parameters.compact_ids);
+        let node_id = self
+            .next_id
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |id| {
+                (id <= max_id).then_some(id + 1)
+            })
+            .map_err(|_| HnswError::IdLimitReached { max_id })?;
+        self.nodes.set(
+            node_id,
+            Node {
+                vector: *vector,
+                levels,
+            },
+        );
+
+        // the read guard is released before locking for write, as the first insertion sets the entry point
+        let current_entry_point = *read(&self.entry_point);
+        let mut entry_point = match current_entry_point {
+            Some(entry_point) => entry_point,
+            None => {
+                let mut guard = write(&self.entry_point);
+                match *guard {
+                    Some(entry_point) => entry_point,
+                    None => {
+                        *guard = Some(EntryPoint {
+                            id: node_id,
+                            level_index: max_level_index,
+                        });
+                        return Ok(node_id);
+                    }
+                }
+            }
+        };
+
+        self.connect_levels(
+            node_id,
+            vector,
+            entry_point,
+            0,
+            max_level_index.min(entry_point.level_index),
+        )?;
+
+        // a node sampled above the top level replaces the entry point once connected, which only locks it for the
+        // swap. If another insertion raised the entry point meanwhile, the node is first connected in the levels added
+        while max_level_index > entry_point.level_index {
+            let mut guard = write(&self.entry_point);
+            let current_entry_point =
+                (*guard).filter(|current| current.level_index > entry_point.level_index);
+
+            let Some(current_entry_point) = current_entry_point else {
+                *guard = Some(EntryPoint {
+                    id: node_id,
+                    level_index: max_level_index,
+                });
+                break;
+            };
+            drop(guard);
+
+            self.connect_levels(
+                node_id,
+                vector,
+                current_entry_point,
+                entry_point.level_index + 1,
+                max_level_index.min(current_entry_point.level_index),
+            )?;
+            entry_point = current_entry_point;
+        }
+
+        Ok(node_id)
+    }
+
+    /// Insert each vector of a slice in the index and return their ids in order, stopping at the first vector that
+    /// cannot be inserted
+    pub fn insert_batch(&self, batch: &[[T; D]]) -> Result<Vec<usize>, HnswError> {
+        batch.iter().map(|vector| self.insert(vector)).collect()
+    }
+
+    /// Search for the k nearest neighbors from the query vector by traveling the index. It can be called while other
+    /// threads insert vectors, which are returned once connected to the graph
+    pub fn search(
+        &self,
+        query: &[T; D],
+        k: usize,
+    ) -> Result<Vec<SearchResult<'_, T, D>>, HnswError> {
+        self.search_with_ef(query, k, self.parameters.ef_search)
+    }
+
+    /// Search for the k nearest neighbors from the query vector, exploring the base level with a dynamic candidate list
+    /// of size `ef` instead of the one set for the index, raised to `k` if smaller
+    pub fn search_with_ef(
+        &self,
+        query: &[T; D],
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<SearchResult<'_, T, D>>, HnswError> {
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        let entry_point = read(&self.entry_point).ok_or(HnswError::EmptyIndex)?;
+
+        graph::search(
+            self,
+            query,
+            entry_point.id,
+            entry_point.level_index,
+            ef.max(k),
+            |_| true,
+        )?
+        .into_iter()
+        .take(k)
+        .map(|c| {
+            Ok(SearchResult::new(
+                c.id,
+                &self.get_node(c.id)?.vector,
+                c.distance,
+            ))
+        })
+        .collect()
+    }
+
+    /// Turn the index into an `HNSW`, once every vector is inserted, to search it and update it
+    pub fn into_index(self) -> HNSW<T, D, F, R> {
+        let rng = self
+            .rng
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut index = HNSW::from_parameters(self.parameters, self.distance_metric, rng);
+
+        let entry_point = self
+            .entry_point
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(entry_point) = entry_point {
+            index.levels = (0..=entry_point.level_index)
+                .map(|level_index| index.empty_level(level_index))
+                .collect();
+            index.entry_point = Some(entry_point.id);
+        }
+
+        for (id, node) in self.nodes.into_nodes() {
+            index.nodes.insert(id, node.vector);
+
+            // a node that failed to be inserted above the top level was not made the entry point
+            for (level, edges) in index.levels.iter_mut().zip(node.levels.into_vec()) {
+                let edges = edges.into_inner().unwrap_or_else(PoisonError::into_inner);
+                level.insert(id);
+                level.set(id, edges);
+            }
+        }
+        index.next_id = self.next_id.into_inner();
+
+        index
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<T, const D: usize, F, R> ConcurrentHNSW<T, D, F, R>
+where
+    T: Sized + Copy + Debug + Send + Sync,
+    F: Fn(&[T], &[T]) -> f64 + Sync,
+    R: Rng + Send,
+{
+    /// Insert each vector of a slice in the index like `insert_batch`, from the threads of the rayon pool. The vectors
+    /// are connected in any order, but their ids are returned in the order of the slice
+    pub fn par_insert_batch(&self, batch: &[[T; D]]) -> Result<Vec<usize>, HnswError> {
+        batch.par_iter().map(|vector| self.insert(vector)).collect()
+    }
+}
+
+impl<T, const D: usize, F, R> Graph<T> for ConcurrentHNSW<T, D, F, R>
+where
+    T: Sized + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    fn vector(&self, id: usize) -> Result<&[T], HnswError> {
+        Ok(&self.get_node(id)?.vector)
+    }
+
+    fn metric(&self, x: &[T], y: &[T]) -> f64 {
+        (self.distance_metric)(x, y)
+    }
+
+    fn for_each_neighbor(
+        &self,
+        level_index: usize,
+        node_id: usize,
+        visit: impl FnMut(usize) -> Result<(), HnswError>,
+    ) -> Result<(), HnswError> {
+        match self.get_node(node_id)?.levels.get(level_index) {
+            Some(edges) => read(edges).iter().copied().try_for_each(visit),
+            None => Ok(()),
+        }
+    }
+
+    fn parameters(&self) -> Parameters {
+        self.parameters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, ConcurrentHNSW, Nodes};
+    use crate::{builder::HnswBuilder, distances::euclidean, error::HnswError};
+    use rand::{rngs::SmallRng, SeedableRng};
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+    };
+
+    const SEED: u64 = 1234;
+
+    #[test]
+    fn test_nodes() {
+        let nodes = Nodes::new();
+        for id in (0..100).rev() {
+            nodes.set(id, id * 10);
+        }
+
+        assert_eq!(Nodes::<usize>::locate(0), (0, 0));
+        assert_eq!(Nodes::<usize>::locate(6), (2, 3));
+        assert_eq!(nodes.get(42), Some(&420));
+        assert_eq!(nodes.get(100), None);
+        assert!(nodes.into_nodes().eq((0..100).map(|id| (id, id * 10))));
+    }
+
+    #[test]
+    fn test_insert() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let index = ConcurrentHNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let ids = thread::scope(|scope| {
+            let handles = (0..4)
+                .map(|i| {
+                    let index = &index;
+                    scope.spawn(move || {
+                        (0..50)
+                            .map(|j| index.insert(&[(i * 50 + j) as f64; 2]).unwrap())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect::<HashSet<_>>()
+        });
+
+        assert_eq!(ids.len(), 200);
+        assert_eq!(index.len(), 200);
+        assert_eq!(
+            index.insert(&[f64::NAN; 2]),
+            Err(HnswError::InvalidDistance)
+        );
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let index = ConcurrentHNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let vectors = (0..100).map(|i| [i as f64; 2]).collect::<Vec<_>>();
+        let ids = index.insert_batch(&vectors).unwrap();
+
+        for (id, vector) in ids.iter().zip(&vectors) {
+            assert_eq!(index.get(*id), Some(vector));
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_par_insert_batch() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let index = ConcurrentHNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let vectors = (0..100).map(|i| [i as f64; 2]).collect::<Vec<_>>();
+        let ids = index.par_insert_batch(&vectors).unwrap();
+
+        for (id, vector) in ids.iter().zip(&vectors) {
+            assert_eq!(index.get(*id), Some(vector));
+        }
+        assert_eq!(index.search(&[10.2; 2], 1).unwrap()[0].id, ids[10]);
+    }
+
+    #[test]
+    fn test_search() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let index = ConcurrentHNSW::new(8, 8, euclidean, rng).unwrap();
+
+        assert_eq!(
+            index.search(&[1.0; 2], 1).unwrap_err(),
+            HnswError::EmptyIndex
+        );
+
+        let vectors = (0..100).map(|i| [i as f64; 2]).collect::<Vec<_>>();
+        let ids = index.insert_batch(&vectors).unwrap();
+        let results = index.search(&[10.2; 2], 3).unwrap();
+
+        let found = results.iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(found, vec![ids[10], ids[11], ids[9]]);
+        assert_eq!(results[0].vector, &[10.0; 2]);
+
+        let index = index.with_ef_search(1).unwrap();
+        assert_eq!(index.search(&[10.2; 2], 1).unwrap()[0].id, ids[10]);
+        assert!(index.with_ef_search(0).is_err());
+    }
+
+    #[test]
+    fn test_search_during_inserts() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let index = ConcurrentHNSW::new(4, 16, euclidean, rng).unwrap();
+        index.insert(&[0.0; 2]).unwrap();
+
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let writers = (0..2)
+                .map(|i| {
+                    let index = &index;
+                    scope.spawn(move || {
+                        for j in 1..=500 {
+                            index.insert(&[(2 * j - i) as f64; 2]).unwrap();
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for _ in 0..2 {
+                let (index, done) = (&index, &done);
+                scope.spawn(move || {
+                    while !done.load(Ordering::Acquire) {
+                        let results = index.search(&[500.2; 2], 5).unwrap();
+                        assert!(!results.is_empty());
+                        assert!(results.windows(2).all(|w| w[0].distance <= w[1].distance));
+                        assert!(results.iter().all(|r| index.get(r.id) == Some(r.vector)));
+
+                        // edges read while they are modified are complete: bounded and without duplicates
+                        for id in 0..index.len() {
+                            let Some(node) = index.nodes.get(id) else {
+                                continue;
+                            };
+                            let edges = read(&node.levels[0]);
+                            assert!(edges.len() <= 8);
+                            assert_eq!(edges.iter().collect::<HashSet<_>>().len(), edges.len());
+                        }
+                    }
+                });
+            }
+
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, Ordering::Release);
+        });
+
+        assert_eq!(index.len(), 1001);
+        assert_eq!(*index.search(&[500.2; 2], 1).unwrap()[0].vector, [500.0; 2]);
+    }
+
+    #[test]
+    fn test_into_index() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let index = ConcurrentHNSW::new(4, 16, euclidean, rng).unwrap();
+
+        let vectors = (0..500).map(|i| [i as f64; 2]).collect::<Vec<_>>();
+        thread::scope(|scope| {
+            for chunk in vectors.chunks(125) {
+                let index = &index;
+                scope.spawn(move || chunk.iter().for_each(|v| _ = index.insert(v).unwrap()));
+            }
+        });
+        let mut index = index.into_index();
+
+        // levels are consistent: the entry point is in the top level, and edges are bounded and point to nodes of the
+        // same level
+        assert_eq!(index.len(), 500);
+        assert!(index
+            .levels
+            .last()
+            .unwrap()
+            .contains(index.entry_point.unwrap()));
+        for (level_index, level) in index.levels.iter().enumerate() {
+            let max_connections = if level_index > 0 { 6 } else { 8 };
+
+            for (_, edges) in level.iter() {
+                assert!(edges.len() > 0 && edges.len() <= max_connections);
+                assert!(edges.clone().all(|id| level.contains(id)));
+            }
+        }
+
+        assert_eq!(*index.search(&[10.2; 2], 1).unwrap()[0].vector, [10.0; 2]);
+        assert_eq!(index.insert(&[0.0; 2]).unwrap(), 500);
+    }
+
+    #[test]
+    fn test_insert_above_top_level() {
+        // a large multiplier makes many nodes raise the top level while others are being inserted
+        let index = HnswBuilder::new(4)
+            .level_multiplier(2.0)
+            .seed(SEED)
+            .build_concurrent(euclidean)
+            .unwrap();
+
+        let vectors = (0..400).map(|i| [i as f64; 2]).collect::<Vec<_>>();
+        thread::scope(|scope| {
+            for chunk in vectors.chunks(50) {
+                let index = &index;
+                scope.spawn(move || chunk.iter().for_each(|v| _ = index.insert(v).unwrap()));
+            }
+        });
+        let entry_point = read(&index.entry_point).unwrap();
+
+        // the entry point is a node of the highest level sampled, and every node is connected in each of its levels
+        // shared with other nodes
+        let levels = (0..400)
+            .map(|id| index.nodes.get(id).unwrap().levels.len())
+            .collect::<Vec<_>>();
+        assert_eq!(levels.iter().max(), Some(&(entry_point.level_index + 1)));
+        for id in 0..400 {
+            let node = index.nodes.get(id).unwrap();
+            for (level_index, edges) in node.levels.iter().enumerate() {
+                let shared = levels.iter().filter(|&&n| n > level_index).count() > 1;
+                assert!(!shared || !read(edges).is_empty());
+            }
+        }
+
+        let index = index.into_index();
+        assert_eq!(index.num_levels(), entry_point.level_index + 1);
+        assert_eq!(*index.search(&[10.2; 2], 1).unwrap()[0].vector, [10.0; 2]);
+    }
+
+    #[test]
+    fn test_insert_level_cap() {
+        // with the largest multiplier, most nodes are sampled far above the top level
+        let index = HnswBuilder::new(4)
+            .level_multiplier(10.0)
+            .seed(SEED)
+            .build_concurrent(euclidean)
+            .unwrap();
+
+        for (id, i) in (0..20).enumerate() {
+            index.insert(&[i as f64; 2]).unwrap();
+
+            // each node raises the top level by one at most
+            assert!(index.nodes.get(id).unwrap().levels.len() <= id + 1);
+            assert!(read(&index.entry_point).unwrap().level_index <= id);
+        }
+    }
+}
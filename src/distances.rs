@@ -34,6 +34,7 @@ where
 }
 
 #[cfg(test)]
+#[allow(clippy::float_equality_without_abs)]
 mod tests {
     use super::{cosine, euclidean};
 
@@ -0,0 +1,360 @@
+use crate::{
+    builder::{self, HnswBuilder, Parameters},
+    error::HnswError,
+    graph::{self, Graph, GraphMut},
+    level::{self, Level},
+};
+use rand::Rng;
+use std::fmt::Debug;
+
+/// Utility struct to store a nearest neighbor search result of an index whose dimension is set at runtime
+#[derive(Debug)]
+pub struct DynamicSearchResult<'v, T> {
+    pub id: usize,
+    pub vector: &'v [T],
+    pub distance: f64,
+}
+
+impl<'v, T> DynamicSearchResult<'v, T> {
+    pub fn new(id: usize, vector: &'v [T], distance: f64) -> Self {
+        Self {
+            id,
+            vector,
+            distance,
+        }
+    }
+}
+
+/// Index of vectors whose dimension is only known at runtime, for instance when it comes from the configuration of an
+/// embedding model. Vectors are passed as slices whose length is checked against the dimension of the index, and are
+/// stored one after another in a single buffer indexed by their id, so that reading a vector during a search costs no
+/// more than with `HNSW`. The levels are traveled by the same search as `HNSW`
+pub struct DynamicHNSW<T, F, R> {
+    parameters: Parameters,
+    dimension: usize,
+    distance_metric: F,
+    rng: R,
+    vectors: Vec<T>, // vector of id `i` at `i * dimension..(i + 1) * dimension`
+    levels: Vec<Level>,
+    entry_point: Option<usize>, // node in the top level where every traversal starts
+}
+
+impl<T, F, R> DynamicHNSW<T, F, R>
+where
+    T: Sized + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    /// Create an empty index of vectors of `dimension` elements, using the parameters recommended in the paper for the
+    /// given `M` and `ef_construction`. Use `HnswBuilder::build_dynamic` to set the other parameters
+    pub fn new(
+        dimension: usize,
+        connections: usize,
+        ef_construction: usize,
+        distance_metric: F,
+        rng: R,
+    ) -> Result<Self, HnswError> {
+        HnswBuilder::new(connections)
+            .ef_construction(ef_construction)
+            .build_dynamic_with_rng(dimension, distance_metric, rng)
+    }
+
+    pub(crate) fn from_parameters(
+        parameters: Parameters,
+        dimension: usize,
+        distance_metric: F,
+        rng: R,
+    ) -> Self {
+        Self {
+            parameters,
+            dimension,
+            distance_metric,
+            rng,
+            vectors: Vec::new(),
+            levels: Vec::new(),
+            entry_point: None,
+        }
+    }
+
+    /// Set the size of the dynamic candidate list used when searching the base level, which must be at least 1
+    pub fn with_ef_search(mut self, ef_search: usize) -> Result<Self, HnswError> {
+        self.parameters.ef_search = builder::check_ef_search(ef_search)?;
+        Ok(self)
+    }
+
+    /// Return the number of elements of the vectors of the index
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// Return true if the index doesn't contain any vector
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    /// Return the number of vectors stored in the index
+    pub fn len(&self) -> usize {
+        self.vectors.len() / self.dimension
+    }
+
+    /// Return the number of levels of the index
+    pub fn num_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Return the vector stored with the given id
+    pub fn get(&self, id: usize) -> Option<&[T]> {
+        let start = id.checked_mul(self.dimension)?;
+        let end = start.checked_add(self.dimension)?;
+        self.vectors.get(start..end)
+    }
+
+    /// Check that a vector has as many elements as the vectors of the index
+    fn check_dimension(&self, vector: &[T]) -> Result<(), HnswError> {
+        if vector.len() == self.dimension {
+            Ok(())
+        } else {
+            Err(HnswError::DimensionMismatch {
+                expected: self.dimension,
+                actual: vector.len(),
+            })
+        }
+    }
+
+    /// Insert a vector in the index and return its unique id, or an error if its length differs from the dimension of
+    /// the index
+    pub fn insert(&mut self, vector: &[T]) -> Result<usize, HnswError> {
+        self.check_dimension(vector)?;
+        self.distance(vector, vector)?;
+
+        let node_id = self.len();
+        level::check_id(node_id, self.parameters.compact_ids)?;
+        self.vectors.extend_from_slice(vector);
+
+        graph::insert_node(self, node_id, vector)?;
+
+        Ok(node_id)
+    }
+
+    /// Insert each vector of an iterator in the index and return their ids in order, stopping at the first vector that
+    /// cannot be inserted
+    pub fn insert_batch<V: AsRef<[T]>>(
+        &mut self,
+        batch: impl IntoIterator<Item = V>,
+    ) -> Result<Vec<usize>, HnswError> {
+        batch
+            .into_iter()
+            .map(|vector| self.insert(vector.as_ref()))
+            .collect()
+    }
+
+    /// Search for the k nearest neighbors from the query vector by traveling the index
+    pub fn search(
+        &self,
+        query: &[T],
+        k: usize,
+    ) -> Result<Vec<DynamicSearchResult<'_, T>>, HnswError> {
+        self.search_with_ef(query, k, self.parameters.ef_search)
+    }
+
+    /// Search for the k nearest neighbors from the query vector, exploring the base level with a dynamic candidate list
+    /// of size `ef` instead of the one set for the index, raised to `k` if smaller
+    pub fn search_with_ef(
+        &self,
+        query: &[T],
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<DynamicSearchResult<'_, T>>, HnswError> {
+        self.check_dimension(query)?;
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        let entry_id = self.entry_point.ok_or(HnswError::EmptyIndex)?;
+
+        graph::search(
+            self,
+            query,
+            entry_id,
+            self.num_levels() - 1,
+            ef.max(k),
+            |_| true,
+        )?
+        .into_iter()
+        .take(k)
+        .map(|c| {
+            Ok(DynamicSearchResult::new(
+                c.id,
+                self.vector(c.id)?,
+                c.distance,
+            ))
+        })
+        .collect()
+    }
+}
+
+impl<T, F, R> Graph<T> for DynamicHNSW<T, F, R>
+where
+    T: Sized + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    fn vector(&self, id: usize) -> Result<&[T], HnswError> {
+        self.get(id).ok_or(HnswError::NodeNotFound(id))
+    }
+
+    fn metric(&self, x: &[T], y: &[T]) -> f64 {
+        (self.distance_metric)(x, y)
+    }
+
+    fn for_each_neighbor(
+        &self,
+        level_index: usize,
+        node_id: usize,
+        visit: impl FnMut(usize) -> Result<(), HnswError>,
+    ) -> Result<(), HnswError> {
+        self.levels[level_index]
+            .get(node_id)
+            .map_or(Ok(()), |mut neighbor_ids| neighbor_ids.try_for_each(visit))
+    }
+
+    fn parameters(&self) -> Parameters {
+        self.parameters
+    }
+}
+
+impl<T, F, R> GraphMut<T> for DynamicHNSW<T, F, R>
+where
+    T: Sized + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    fn levels(&self) -> &[Level] {
+        &self.levels
+    }
+
+    fn levels_mut(&mut self) -> &mut Vec<Level> {
+        &mut self.levels
+    }
+
+    fn entry_point(&self) -> Option<usize> {
+        self.entry_point
+    }
+
+    fn set_entry_point(&mut self, id: usize) {
+        self.entry_point = Some(id);
+    }
+
+    fn sample_max_level_index(&mut self) -> usize {
+        graph::sample_level_index(&mut self.rng, self.parameters.level_multiplier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DynamicHNSW;
+    use crate::{builder::HnswBuilder, distances::euclidean, error::HnswError};
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    const SEED: u64 = 1234;
+
+    #[test]
+    fn test_insert() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = DynamicHNSW::new(3, 8, 16, euclidean, rng).unwrap();
+
+        let ids = index
+            .insert_batch([vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]])
+            .unwrap();
+        assert_eq!(ids, [0, 1]);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get(1), Some(&[4.0, 5.0, 6.0][..]));
+        assert_eq!(index.get(2), None);
+
+        // ids whose vector would start or end past the largest index
+        assert_eq!(index.get(usize::MAX / 2), None);
+        assert_eq!(index.get(usize::MAX / 3), None);
+        assert_eq!(index.get(usize::MAX), None);
+
+        assert_eq!(
+            index.insert(&[1.0, 2.0]),
+            Err(HnswError::DimensionMismatch {
+                expected: 3,
+                actual: 2
+            })
+        );
+        assert_eq!(index.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_dimension() {
+        let builder = HnswBuilder::new(8);
+
+        assert!(matches!(
+            builder.build_dynamic::<f64, _>(0, euclidean),
+            Err(HnswError::InvalidParameter {
+                name: "dimension",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_search() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = DynamicHNSW::new(2, 8, 16, euclidean, rng).unwrap();
+        assert_eq!(
+            index.search(&[0.0; 2], 1).unwrap_err(),
+            HnswError::EmptyIndex
+        );
+
+        index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+
+        let results = index.search(&[41.8, 41.8], 2).unwrap();
+        assert_eq!(results[0].id, 42);
+        assert_eq!(results[0].vector, [42.0, 42.0]);
+        assert_eq!(results[1].id, 41);
+        assert!(index.search_with_ef(&[41.8; 2], 0, 0).unwrap().is_empty());
+
+        assert_eq!(
+            index.search(&[1.0; 3], 1).unwrap_err(),
+            HnswError::DimensionMismatch {
+                expected: 2,
+                actual: 3
+            }
+        );
+
+        let index = index.with_ef_search(1).unwrap();
+        assert_eq!(index.search(&[41.8; 2], 1).unwrap()[0].id, 42);
+        assert!(index.with_ef_search(0).is_err());
+    }
+
+    #[test]
+    fn test_same_graph_as_hnsw() {
+        let mut rng = SmallRng::seed_from_u64(SEED);
+        let vectors = (0..500)
+            .map(|_| [(); 16].map(|_| rng.gen_range(-1.0..1.0)))
+            .collect::<Vec<[f64; 16]>>();
+
+        let builder = HnswBuilder::new(8).ef_construction(32).seed(SEED);
+        let mut index = builder.build(euclidean).unwrap();
+        let mut dynamic_index = builder.build_dynamic(16, euclidean).unwrap();
+        index.insert_batch(vectors.iter().copied()).unwrap();
+        dynamic_index.insert_batch(&vectors).unwrap();
+
+        // the same insertions and searches run on the same vectors
+        assert_eq!(dynamic_index.num_levels(), index.num_levels());
+        for query in vectors.iter().step_by(20) {
+            let found = dynamic_index.search(query, 10).unwrap();
+            let expected = index.search(query, 10).unwrap();
+
+            assert_eq!(
+                found.iter().map(|r| (r.id, r.distance)).collect::<Vec<_>>(),
+                expected
+                    .iter()
+                    .map(|r| (r.id, r.distance))
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::{error::Error, fmt, io};
+
+/// Errors returned by the index
+#[derive(Debug, Clone, PartialEq)]
+pub enum HnswError {
+    /// A construction parameter is out of its valid range, or inconsistent with another parameter
+    InvalidParameter {
+        name: &'static str,
+        reason: &'static str,
+    },
+    /// The index does not contain any vector to search from
+    EmptyIndex,
+    /// No vector is stored under this id
+    NodeNotFound(usize),
+    /// The length of a vector differs from the dimension of the index
+    DimensionMismatch { expected: usize, actual: usize },
+    /// The index cannot assign ids larger than this one, see `HnswBuilder::compact_ids`
+    IdLimitReached { max_id: usize },
+    /// The distance metric returned NaN, which cannot be ordered
+    InvalidDistance,
+    /// A vector is already stored under this key
+    DuplicateKey,
+    /// No vector is stored under this key
+    KeyNotFound,
+    /// Reading or writing an index file failed
+    Io(io::ErrorKind),
+    /// The file is not an index, or an index of other vectors
+    InvalidFormat(&'static str),
+    /// The file was written with a version of the format that cannot be read
+    UnsupportedVersion(u32),
+    /// The content of the file doesn't match its checksum, it is corrupted
+    ChecksumMismatch,
+}
+
+impl fmt::Display for HnswError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidParameter { name, reason } => {
+                write!(f, "invalid parameter `{name}`: {reason}")
+            }
+            Self::EmptyIndex => write!(f, "index is empty"),
+            Self::NodeNotFound(id) => write!(f, "no vector stored with id {id}"),
+            Self::DimensionMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "vector of length {actual} in an index of dimension {expected}"
+                )
+            }
+            Self::IdLimitReached { max_id } => {
+                write!(f, "id limit reached, ids cannot be larger than {max_id}")
+            }
+            Self::InvalidDistance => write!(f, "distance metric returned NaN"),
+            Self::DuplicateKey => write!(f, "a vector is already stored with this key"),
+            Self::KeyNotFound => write!(f, "no vector stored with this key"),
+            Self::Io(kind) => write!(f, "i/o error: {kind}"),
+            Self::InvalidFormat(reason) => write!(f, "invalid index file: {reason}"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported index file version {version}")
+            }
+            Self::ChecksumMismatch => write!(f, "index file is corrupted, checksum mismatch"),
+        }
+    }
+}
+
+impl Error for HnswError {}
+
+impl From<io::Error> for HnswError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error.kind())
+    }
+}
@@ -0,0 +1,1072 @@
+//! Binary format of the index files written by `HNSW::save`.
+//!
+//! Every number is stored in little endian, whatever the platform, and every section starts at an offset multiple of 8
+//! bytes so that a file can be searched in place once mapped in memory. Nodes are stored in increasing order of their
+//! id, and designated in the graph by their position in that order, their rank. A file is made of:
+//!
+//! - a header of 136 bytes:
+//!
+//! | offset | type      | content                                                          |
+//! |--------|-----------|------------------------------------------------------------------|
+//! | 0      | `[u8; 8]` | magic bytes `RSHNSW\0\0`                                         |
+//! | 8      | `u32`     | version of the format, currently 1                               |
+//! | 12     | `u32`     | type of the vector elements, see `Element`                       |
+//! | 16     | `u64`     | dimension of the vectors                                         |
+//! | 24     | `u64`     | `M`                                                              |
+//! | 32     | `u64`     | `Mmax`                                                           |
+//! | 40     | `u64`     | `Mmax0`                                                          |
+//! | 48     | `f64`     | `m_L`                                                            |
+//! | 56     | `u64`     | `ef_construction`                                                |
+//! | 64     | `u64`     | `ef_search`                                                      |
+//! | 72     | `u32`     | neighbor selection: 0 if simple, otherwise `1 \| extend_candidates << 1 \| keep_pruned_connections << 2` |
+//! | 76     | `u32`     | pruning, encoded as the neighbor selection                       |
+//! | 80     | `u64`     | number of nodes                                                  |
+//! | 88     | `u64`     | number of levels                                                 |
+//! | 96     | `u64`     | rank of the entry point, `u64::MAX` if the index is empty        |
+//! | 104    | `u64`     | next id to assign                                                |
+//! | 112    | `u64`     | length of the body, following the header                         |
+//! | 120    | `u64`     | width of the ids in the edges of the loaded index: 32 if built with `compact_ids`, 64 otherwise |
+//! | 128    | `u32`     | CRC-32 of the body                                               |
+//! | 132    | `u32`     | CRC-32 of the 132 previous bytes of the header                   |
+//!
+//! - the body, made of the following sections, each padded with zeros to a multiple of 8 bytes:
+//!   - for each level from the base one, its number of nodes and its number of edges, as two `u64`
+//!   - the id of each node, as `u64`
+//!   - the vector of each node, as `D` consecutive elements
+//!   - for each node, 1 if it is marked as deleted and 0 otherwise, as `u8`
+//!   - for each level from the base one:
+//!     - the rank of the nodes belonging to the level in increasing order, as `u64`
+//!     - for each of these nodes, the position of its first edge in the edges of the level, followed by the total
+//!       number of edges, as `u64`
+//!     - the rank of the neighbors of each node, as `u64`
+//!
+//! The CRC-32 uses the polynomial of zlib and PNG (IEEE 802.3).
+
+use crate::{
+    builder::Parameters,
+    error::HnswError,
+    hnsw::{NeighborSelection, HNSW},
+    level,
+};
+use rand::Rng;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::Debug,
+    fs::{self, File},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    mem,
+    ops::Deref,
+    path::Path,
+};
+
+const MAGIC: [u8; 8] = *b"RSHNSW\0\0";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 136;
+const NO_ENTRY_POINT: u64 = u64::MAX;
+const ALIGNMENT: usize = 8;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Numeric types that vectors can be made of to be saved, along with the code identifying them in the files. The types
+/// implementing it are stored in memory exactly as their bytes in the file on little endian platforms
+pub trait Element: sealed::Sealed + Copy + Sized {
+    const CODE: u32;
+
+    /// Append the little endian bytes of the element
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>);
+
+    /// Read an element from its little endian bytes
+    fn from_le_slice(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_element {
+    ($($element:ty => $code:expr),* $(,)?) => {
+        $(
+            impl sealed::Sealed for $element {}
+
+            impl Element for $element {
+                const CODE: u32 = $code;
+
+                fn extend_le_bytes(self, bytes: &mut Vec<u8>) {
+                    bytes.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn from_le_slice(bytes: &[u8]) -> Self {
+                    let mut le_bytes = [0; mem::size_of::<$element>()];
+                    le_bytes.copy_from_slice(bytes);
+                    Self::from_le_bytes(le_bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_element!(
+    f32 => 1,
+    f64 => 2,
+    i8 => 3,
+    u8 => 4,
+    i16 => 5,
+    u16 => 6,
+    i32 => 7,
+    u32 => 8,
+    i64 => 9,
+    u64 => 10,
+);
+
+/// Lookup table of the CRC-32, computed at compile time
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+/// Incremental CRC-32 of a sequence of bytes
+#[derive(Debug, Clone, Copy)]
+struct Crc32(u32);
+
+impl Crc32 {
+    fn new() -> Self {
+        Self(u32::MAX)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = CRC_TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finalize(self) -> u32 {
+        !self.0
+    }
+
+    fn checksum(bytes: &[u8]) -> u32 {
+        let mut crc = Self::new();
+        crc.update(bytes);
+        crc.finalize()
+    }
+}
+
+fn padding(length: usize) -> usize {
+    length.next_multiple_of(ALIGNMENT) - length
+}
+
+fn encode_selection(selection: NeighborSelection) -> u32 {
+    match selection {
+        NeighborSelection::Simple => 0,
+        NeighborSelection::Heuristic {
+            extend_candidates,
+            keep_pruned_connections,
+        } => 1 | (extend_candidates as u32) << 1 | (keep_pruned_connections as u32) << 2,
+    }
+}
+
+fn decode_selection(code: u32) -> Result<NeighborSelection, HnswError> {
+    match code {
+        0 => Ok(NeighborSelection::Simple),
+        1..=7 if code & 1 == 1 => Ok(NeighborSelection::Heuristic {
+            extend_candidates: code & 2 != 0,
+            keep_pruned_connections: code & 4 != 0,
+        }),
+        _ => Err(HnswError::InvalidFormat("unknown neighbor selection")),
+    }
+}
+
+/// Content of the header of an index file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Header {
+    pub element: u32,
+    pub dimension: usize,
+    pub parameters: Parameters,
+    pub num_nodes: usize,
+    pub num_levels: usize,
+    pub entry_point: Option<usize>,
+    pub next_id: usize,
+    pub body_length: usize,
+    pub body_checksum: u32,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        let parameters = &self.parameters;
+
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.element.to_le_bytes());
+        for value in [
+            self.dimension,
+            parameters.connections,
+            parameters.max_connections,
+            parameters.max_connections_0,
+        ] {
+            bytes.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+        bytes.extend_from_slice(&parameters.level_multiplier.to_le_bytes());
+        for value in [parameters.ef_construction, parameters.ef_search] {
+            bytes.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+        bytes.extend_from_slice(&encode_selection(parameters.neighbor_selection).to_le_bytes());
+        bytes.extend_from_slice(&encode_selection(parameters.pruning).to_le_bytes());
+        for value in [
+            self.num_nodes as u64,
+            self.num_levels as u64,
+            self.entry_point.map_or(NO_ENTRY_POINT, |rank| rank as u64),
+            self.next_id as u64,
+            self.body_length as u64,
+            if parameters.compact_ids { 32 } else { 64 },
+        ] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.body_checksum.to_le_bytes());
+        bytes.extend_from_slice(&Crc32::checksum(&bytes).to_le_bytes());
+
+        bytes
+            .try_into()
+            .expect("the header fields add up to its size")
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, HnswError> {
+        if bytes.len() < HEADER_SIZE || bytes[..8] != MAGIC {
+            return Err(HnswError::InvalidFormat("not an index file"));
+        }
+
+        let version = read_u32(bytes, 8);
+        if version != VERSION {
+            return Err(HnswError::UnsupportedVersion(version));
+        }
+        if Crc32::checksum(&bytes[..132]) != read_u32(bytes, 132) {
+            return Err(HnswError::ChecksumMismatch);
+        }
+
+        let parameters = Parameters {
+            connections: read_usize(bytes, 24)?,
+            max_connections: read_usize(bytes, 32)?,
+            max_connections_0: read_usize(bytes, 40)?,
+            level_multiplier: f64::from_bits(read_u64(bytes, 48)),
+            ef_construction: read_usize(bytes, 56)?,
+            ef_search: read_usize(bytes, 64)?,
+            neighbor_selection: decode_selection(read_u32(bytes, 72))?,
+            pruning: decode_selection(read_u32(bytes, 76))?,
+            compact_ids: match read_u64(bytes, 120) {
+                32 => true,
+                64 => false,
+                _ => return Err(HnswError::InvalidFormat("unknown id width")),
+            },
+        }
+        .validate()?;
+
+        let entry_point = match read_u64(bytes, 96) {
+            NO_ENTRY_POINT => None,
+            _ => Some(read_usize(bytes, 96)?),
+        };
+
+        Ok(Self {
+            element: read_u32(bytes, 12),
+            dimension: read_usize(bytes, 16)?,
+            parameters,
+            num_nodes: read_usize(bytes, 80)?,
+            num_levels: read_usize(bytes, 88)?,
+            entry_point,
+            next_id: read_usize(bytes, 104)?,
+            body_length: read_usize(bytes, 112)?,
+            body_checksum: read_u32(bytes, 128),
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+}
+
+fn read_usize(bytes: &[u8], offset: usize) -> Result<usize, HnswError> {
+    usize::try_from(read_u64(bytes, offset))
+        .map_err(|_| HnswError::InvalidFormat("value too large for the platform"))
+}
+
+fn verify_checksum(bytes: &[u8], header: &Header) -> Result<(), HnswError> {
+    if Crc32::checksum(&bytes[HEADER_SIZE..]) == header.body_checksum {
+        Ok(())
+    } else {
+        Err(HnswError::ChecksumMismatch)
+    }
+}
+
+/// Offsets of the sections of a level in the file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LevelLayout {
+    pub num_members: usize,
+    pub num_edges: usize,
+    pub members: usize,
+    pub offsets: usize,
+    pub edges: usize,
+}
+
+/// Offsets of the sections of an index file, from its start
+#[derive(Debug, Clone)]
+pub(crate) struct Layout {
+    pub ids: usize,
+    pub vectors: usize,
+    pub deleted: usize,
+    pub levels: Vec<LevelLayout>,
+    pub end: usize,
+}
+
+impl Layout {
+    /// Compute the offsets of the sections from the number of nodes and the number of nodes and edges in each level.
+    /// Returns `None` if the sections don't fit in memory
+    fn new(num_nodes: usize, vector_size: usize, level_sizes: &[(usize, usize)]) -> Option<Self> {
+        let mut offset = HEADER_SIZE;
+        let mut section = |length: usize| {
+            let start = offset;
+            offset = offset.checked_add(length)?;
+            offset = offset.checked_add(padding(offset))?;
+            Some(start)
+        };
+
+        section(level_sizes.len().checked_mul(16)?)?;
+        let ids = section(num_nodes.checked_mul(8)?)?;
+        let vectors = section(num_nodes.checked_mul(vector_size)?)?;
+        let deleted = section(num_nodes)?;
+        let levels = level_sizes
+            .iter()
+            .map(|&(num_members, num_edges)| {
+                Some(LevelLayout {
+                    num_members,
+                    num_edges,
+                    members: section(num_members.checked_mul(8)?)?,
+                    offsets: section(num_members.checked_add(1)?.checked_mul(8)?)?,
+                    edges: section(num_edges.checked_mul(8)?)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            ids,
+            vectors,
+            deleted,
+            levels,
+            end: offset,
+        })
+    }
+}
+
+/// View over the bytes of an index file, either read in memory or mapped, checked to be consistent with its header
+pub(crate) struct IndexFile<B> {
+    pub header: Header,
+    pub layout: Layout,
+    bytes: B,
+}
+
+impl<B: Deref<Target = [u8]>> IndexFile<B> {
+    /// Check the header of a file storing vectors of `dimension` elements of type `T` and compute the layout of its
+    /// sections. The checksum of the body is only verified if `verify_body` is true, as it requires reading all of it
+    pub fn parse<T: Element>(
+        bytes: B,
+        dimension: usize,
+        verify_body: bool,
+    ) -> Result<Self, HnswError> {
+        let header = Header::decode(&bytes)?;
+
+        if header.element != T::CODE {
+            return Err(HnswError::InvalidFormat("vectors are of another type"));
+        }
+        if header.dimension != dimension {
+            return Err(HnswError::InvalidFormat("vectors are of another dimension"));
+        }
+        if Some(header.body_length) != bytes.len().checked_sub(HEADER_SIZE) {
+            return Err(HnswError::InvalidFormat("file is truncated"));
+        }
+        if verify_body {
+            verify_checksum(&bytes, &header)?;
+        }
+
+        let level_sizes = (0..header.num_levels)
+            .map(|level_index| {
+                let offset = HEADER_SIZE + 16 * level_index;
+                if offset + 16 > bytes.len() {
+                    return Err(HnswError::InvalidFormat("file is truncated"));
+                }
+                Ok((read_usize(&bytes, offset)?, read_usize(&bytes, offset + 8)?))
+            })
+            .collect::<Result<Vec<_>, HnswError>>()?;
+
+        let vector_size = dimension * mem::size_of::<T>();
+        let layout = Layout::new(header.num_nodes, vector_size, &level_sizes)
+            .filter(|layout| layout.end == bytes.len())
+            .ok_or(HnswError::InvalidFormat(
+                "sections don't match the file length",
+            ))?;
+
+        if header
+            .entry_point
+            .is_some_and(|rank| rank >= header.num_nodes)
+        {
+            return Err(HnswError::InvalidFormat("entry point out of bounds"));
+        }
+
+        Ok(Self {
+            header,
+            layout,
+            bytes,
+        })
+    }
+
+    /// Verify the checksum of the body of the file, which requires reading all of it
+    pub fn verify_body(&self) -> Result<(), HnswError> {
+        verify_checksum(&self.bytes, &self.header)
+    }
+
+    /// Return the rank stored at a position of a section of `u64`, checked to designate a node
+    fn rank_at(&self, offset: usize) -> Result<usize, HnswError> {
+        read_usize(&self.bytes, offset)
+            .ok()
+            .filter(|&rank| rank < self.header.num_nodes)
+            .ok_or(HnswError::InvalidFormat("node rank out of bounds"))
+    }
+
+    /// Return the id of the node of a given rank
+    pub fn id(&self, rank: usize) -> Result<usize, HnswError> {
+        read_usize(&self.bytes, self.layout.ids + 8 * rank)
+    }
+
+    /// Return the rank of the node with a given id, if any, by binary search among the sorted ids
+    pub fn rank(&self, id: usize) -> Result<Option<usize>, HnswError> {
+        let (mut low, mut high) = (0, self.header.num_nodes);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.id(middle)?.cmp(&id) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Ok(Some(middle)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Return the bytes of the vector of the node of a given rank
+    pub fn vector_bytes(&self, rank: usize, vector_size: usize) -> &[u8] {
+        let start = self.layout.vectors + rank * vector_size;
+        &self.bytes[start..start + vector_size]
+    }
+
+    /// Return true if the node of a given rank is marked as deleted
+    pub fn is_deleted(&self, rank: usize) -> bool {
+        self.bytes[self.layout.deleted + rank] != 0
+    }
+
+    /// Return the rank of the node at a given position among the nodes of a level
+    pub fn member(&self, level_index: usize, position: usize) -> Result<usize, HnswError> {
+        self.rank_at(self.layout.levels[level_index].members + 8 * position)
+    }
+
+    /// Return the position of a node among the nodes of a level, if it belongs to it. All nodes belong to the base
+    /// level, in which the position of a node is its rank
+    pub fn position(&self, level_index: usize, rank: usize) -> Result<Option<usize>, HnswError> {
+        let Some(level) = self.layout.levels.get(level_index) else {
+            return Ok(None);
+        };
+        if level_index == 0 {
+            return Ok((rank < level.num_members).then_some(rank));
+        }
+
+        // binary search among the sorted ranks of the members of the level
+        let (mut low, mut high) = (0, level.num_members);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.member(level_index, middle)?.cmp(&rank) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Ok(Some(middle)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Check that the levels can be traveled before any of them is loaded: the base level holds every node, the nodes
+    /// of a level belong to the level below, their edges stay within the level and within the maximum number of
+    /// connections, and the entry point belongs to the top level
+    fn check_levels(&self) -> Result<(), HnswError> {
+        let invalid = |reason| Err(HnswError::InvalidFormat(reason));
+        let parameters = &self.header.parameters;
+
+        if self
+            .layout
+            .levels
+            .first()
+            .is_some_and(|level| level.num_members != self.header.num_nodes)
+        {
+            return invalid("nodes missing from the base level");
+        }
+
+        for (level_index, level) in self.layout.levels.iter().enumerate() {
+            let max_connections = if level_index > 0 {
+                parameters.max_connections
+            } else {
+                parameters.max_connections_0
+            };
+            let mut previous_rank = None;
+
+            for position in 0..level.num_members {
+                let rank = self.member(level_index, position)?;
+                if previous_rank.is_some_and(|previous_rank| previous_rank >= rank) {
+                    return invalid("nodes of a level not in increasing order");
+                }
+                previous_rank = Some(rank);
+
+                if level_index > 0 && self.position(level_index - 1, rank)?.is_none() {
+                    return invalid("node missing from a lower level");
+                }
+
+                let mut num_edges = 0;
+                self.for_each_edge(level_index, position, |neighbor_rank| {
+                    num_edges += 1;
+                    match self.position(level_index, neighbor_rank)? {
+                        Some(_) => Ok(()),
+                        None => invalid("edge towards a node outside of the level"),
+                    }
+                })?;
+                if num_edges > max_connections {
+                    return invalid("more edges than the maximum number of connections");
+                }
+            }
+        }
+
+        match (
+            self.header.entry_point,
+            self.layout.levels.len().checked_sub(1),
+        ) {
+            (None, None) => Ok(()),
+            (Some(rank), Some(top_level_index))
+                if self.position(top_level_index, rank)?.is_some() =>
+            {
+                Ok(())
+            }
+            _ => invalid("entry point outside of the top level"),
+        }
+    }
+
+    /// Call `visit` with the rank of each neighbor of the node at a given position in a level
+    pub fn for_each_edge(
+        &self,
+        level_index: usize,
+        position: usize,
+        mut visit: impl FnMut(usize) -> Result<(), HnswError>,
+    ) -> Result<(), HnswError> {
+        let level = &self.layout.levels[level_index];
+        let start = read_usize(&self.bytes, level.offsets + 8 * position)?;
+        let end = read_usize(&self.bytes, level.offsets + 8 * (position + 1))?;
+
+        if start > end || end > level.num_edges {
+            return Err(HnswError::InvalidFormat("edges out of bounds"));
+        }
+
+        for edge in start..end {
+            visit(self.rank_at(level.edges + 8 * edge)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writer computing the CRC-32 of the bytes written through it
+struct ChecksumWriter<W> {
+    writer: W,
+    crc: Crc32,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn write_section(&mut self, bytes: &[u8]) -> Result<(), HnswError> {
+        let zeros = [0; ALIGNMENT];
+
+        for bytes in [bytes, &zeros[..padding(bytes.len())]] {
+            self.crc.update(bytes);
+            self.writer.write_all(bytes)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_u64s(&mut self, values: impl Iterator<Item = usize>) -> Result<(), HnswError> {
+        let bytes = values
+            .flat_map(|value| (value as u64).to_le_bytes())
+            .collect::<Vec<_>>();
+        self.write_section(&bytes)
+    }
+}
+
+impl<T, const D: usize, F, R> HNSW<T, D, F, R>
+where
+    T: Element + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    /// Write the index to a file, in the binary format documented in the `format` module. The distance metric and the
+    /// random number generator are not saved. The index is written to a temporary file next to the target one, which
+    /// is then renamed, so that the previous file is left untouched if writing fails
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), HnswError> {
+        let path = path.as_ref();
+        let mut temporary_name = path
+            .file_name()
+            .ok_or(HnswError::Io(io::ErrorKind::InvalidInput))?
+            .to_owned();
+        temporary_name.push(".tmp");
+        let temporary_path = path.with_file_name(temporary_name);
+
+        let result = File::create(&temporary_path)
+            .map_err(HnswError::from)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                self.write(&mut writer)?;
+                writer.flush()?;
+                writer.get_ref().sync_all()?;
+                Ok(())
+            })
+            .and_then(|()| Ok(fs::rename(&temporary_path, path)?));
+        if result.is_err() {
+            _ = fs::remove_file(&temporary_path);
+        }
+
+        result
+    }
+
+    fn write<W: Write + Seek>(&self, writer: &mut W) -> Result<(), HnswError> {
+        let ids = self.nodes.ids().collect::<Vec<_>>();
+
+        let ranks = ids
+            .iter()
+            .enumerate()
+            .map(|(rank, &id)| (id, rank))
+            .collect::<HashMap<_, _>>();
+        let rank = |id: &usize| ranks.get(id).copied().ok_or(HnswError::NodeNotFound(*id));
+
+        // members of each level sorted by rank, with the ranks of their neighbors
+        let levels = self
+            .levels
+            .iter()
+            .map(|level| {
+                let mut members = level
+                    .iter()
+                    .map(|(id, edges)| {
+                        Ok((
+                            rank(&id)?,
+                            edges.map(|id| rank(&id)).collect::<Result<Vec<_>, _>>()?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, HnswError>>()?;
+                members.sort_unstable_by_key(|(rank, _)| *rank);
+                Ok(members)
+            })
+            .collect::<Result<Vec<_>, HnswError>>()?;
+
+        let level_sizes = levels
+            .iter()
+            .map(|members| {
+                let num_edges = members.iter().map(|(_, edges)| edges.len()).sum();
+                (members.len(), num_edges)
+            })
+            .collect::<Vec<_>>();
+
+        // the header is written last as it holds the checksum of the body
+        writer.write_all(&[0; HEADER_SIZE])?;
+        let mut body = ChecksumWriter {
+            writer: &mut *writer,
+            crc: Crc32::new(),
+        };
+
+        body.write_u64s(
+            level_sizes
+                .iter()
+                .flat_map(|&(members, edges)| [members, edges]),
+        )?;
+        body.write_u64s(ids.iter().copied())?;
+
+        let mut vectors = Vec::with_capacity(ids.len() * D * mem::size_of::<T>());
+        for id in &ids {
+            for &element in &self.nodes[*id] {
+                element.extend_le_bytes(&mut vectors);
+            }
+        }
+        body.write_section(&vectors)?;
+
+        let deleted = ids
+            .iter()
+            .map(|id| self.deleted.contains(id) as u8)
+            .collect::<Vec<_>>();
+        body.write_section(&deleted)?;
+
+        for (members, &(_, num_edges)) in levels.iter().zip(&level_sizes) {
+            body.write_u64s(members.iter().map(|(rank, _)| *rank))?;
+
+            let offsets = members.iter().scan(0, |offset, (_, edges)| {
+                let start = *offset;
+                *offset += edges.len();
+                Some(start)
+            });
+            body.write_u64s(offsets.chain([num_edges]))?;
+
+            body.write_u64s(members.iter().flat_map(|(_, edges)| edges.iter().copied()))?;
+        }
+
+        let body_checksum = body.crc.finalize();
+        let body_length = writer.stream_position()? as usize - HEADER_SIZE;
+
+        let header = Header {
+            element: T::CODE,
+            dimension: D,
+            parameters: self.parameters(),
+            num_nodes: ids.len(),
+            num_levels: levels.len(),
+            entry_point: self.entry_point.map(|id| rank(&id)).transpose()?,
+            next_id: self.next_id,
+            body_length,
+            body_checksum,
+        };
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(&header.encode())?;
+
+        Ok(())
+    }
+
+    /// Read an index written by `save`, using the given distance metric, which should be the one the index was built
+    /// with, and random number generator. Files that are not an index of vectors of the same type and dimension, or
+    /// whose content doesn't match its checksum, are rejected
+    pub fn load(path: impl AsRef<Path>, distance_metric: F, rng: R) -> Result<Self, HnswError> {
+        let bytes = fs::read(path)?;
+        let file = IndexFile::parse::<T>(bytes.as_slice(), D, true)?;
+        let header = &file.header;
+
+        let mut index = Self::from_parameters(header.parameters, distance_metric, rng);
+        let vector_size = D * mem::size_of::<T>();
+
+        // nothing is stored before the ids and the levels are checked, and the storage sized by the largest id is
+        // allocated up front so that ids too large for the memory available are an error rather than an abort
+        let ids = (0..header.num_nodes)
+            .map(|rank| file.id(rank))
+            .collect::<Result<Vec<_>, HnswError>>()?;
+        if ids.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(HnswError::InvalidFormat("ids not in increasing order"));
+        }
+        if let Some(&max_id) = ids.last() {
+            if max_id >= header.next_id {
+                return Err(HnswError::InvalidFormat("id larger than the next id"));
+            }
+            level::check_id(max_id, header.parameters.compact_ids)?;
+        }
+        file.check_levels()?;
+
+        let num_slots = ids.last().map_or(0, |&max_id| max_id + 1);
+        let out_of_memory = |_| HnswError::Io(io::ErrorKind::OutOfMemory);
+        index.nodes.try_reserve(num_slots).map_err(out_of_memory)?;
+
+        for (rank, &id) in ids.iter().enumerate() {
+            let mut vector = file
+                .vector_bytes(rank, vector_size)
+                .chunks_exact(mem::size_of::<T>())
+                .map(T::from_le_slice);
+            let vector = [(); D].map(|_| vector.next().expect("D elements per vector"));
+
+            index.nodes.insert(id, vector);
+            if file.is_deleted(rank) {
+                index.deleted.insert(id);
+            }
+        }
+
+        for (level_index, layout) in file.layout.levels.iter().enumerate() {
+            let mut level = index.empty_level(level_index);
+            if level_index == 0 {
+                level.try_reserve(num_slots).map_err(out_of_memory)?;
+            }
+
+            for position in 0..layout.num_members {
+                let mut edges = Vec::new();
+                file.for_each_edge(level_index, position, |rank| {
+                    edges.push(ids[rank]);
+                    Ok(())
+                })?;
+
+                let id = ids[file.member(level_index, position)?];
+                level.insert(id);
+                level.set(id, edges);
+            }
+
+            index.levels.push(level);
+        }
+
+        index.entry_point = header.entry_point.map(|rank| ids[rank]);
+        index.next_id = header.next_id;
+
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Crc32, Element, IndexFile, HEADER_SIZE};
+    use crate::{builder::HnswBuilder, distances::euclidean, error::HnswError, hnsw::HNSW};
+    use rand::{rngs::SmallRng, SeedableRng};
+    use std::{
+        env,
+        fmt::Debug,
+        fs, io,
+        iter::Sum,
+        ops::{Mul, Sub},
+        path::PathBuf,
+        process,
+    };
+
+    const SEED: u64 = 1234;
+
+    type Index<T, const D: usize> = HNSW<T, D, fn(&[T], &[T]) -> f64, SmallRng>;
+
+    /// Path of a file in the temporary directory, unique to a test and to this process
+    fn temporary_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rust-hnsw-{}-{name}.bin", process::id()))
+    }
+
+    fn build_index() -> Index<f32, 2> {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = Index::<f32, 2>::new(8, 16, euclidean, rng).unwrap();
+
+        let ids = index.insert_batch((0..200).map(|i| [i as f32; 2])).unwrap();
+        index.remove(ids[5]).unwrap();
+        index.mark_deleted(ids[6]).unwrap();
+
+        index
+    }
+
+    fn load<T, const D: usize>(path: &PathBuf) -> Result<Index<T, D>, HnswError>
+    where
+        T: Element + Debug + Sub<Output = T> + Mul<Output = T> + Sum + Into<f64>,
+    {
+        Index::load(path, euclidean, SmallRng::seed_from_u64(SEED))
+    }
+
+    #[test]
+    fn test_crc32() {
+        // check value of the CRC-32 used by zlib
+        assert_eq!(Crc32::checksum(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = temporary_path("save-load");
+        let index = build_index();
+        index.save(&path).unwrap();
+
+        let loaded = load::<f32, 2>(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.nodes, index.nodes);
+        assert_eq!(loaded.levels, index.levels);
+        assert_eq!(loaded.entry_point, index.entry_point);
+        assert_eq!(loaded.deleted, index.deleted);
+        assert_eq!(loaded.next_id, index.next_id);
+        assert_eq!(loaded.max_connections_0, index.max_connections_0);
+        assert!(!loaded.parameters().compact_ids);
+
+        let found = loaded.search(&[6.2; 2], 3).unwrap();
+        let expected = index.search(&[6.2; 2], 3).unwrap();
+        assert!(found.iter().zip(&expected).all(|(f, e)| f.id == e.id));
+    }
+
+    #[test]
+    fn test_save_load_compact_ids() {
+        let path = temporary_path("compact-ids");
+        let mut index = HnswBuilder::new(8)
+            .compact_ids(true)
+            .seed(SEED)
+            .build(euclidean)
+            .unwrap();
+        index.insert_batch((0..100).map(|i| [i as f32; 2])).unwrap();
+        index.save(&path).unwrap();
+
+        let mut loaded: Index<f32, 2> = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // the levels keep storing ids on 32 bits
+        assert!(loaded.parameters().compact_ids);
+        assert_eq!(loaded.levels, index.levels);
+        loaded.next_id = u32::MAX as usize;
+        assert_eq!(
+            loaded.insert(&[0.5; 2]),
+            Err(HnswError::IdLimitReached {
+                max_id: u32::MAX as usize - 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_load_empty() {
+        let path = temporary_path("empty");
+        let index = Index::<f64, 3>::new(8, 16, euclidean, SmallRng::seed_from_u64(SEED)).unwrap();
+        index.save(&path).unwrap();
+
+        let loaded = load::<f64, 3>(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_empty());
+        assert_eq!(
+            loaded.search(&[1.0; 3], 1).unwrap_err(),
+            HnswError::EmptyIndex
+        );
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let path = temporary_path("invalid");
+        build_index().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let load_bytes = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            load::<f32, 2>(&path).err()
+        };
+
+        // any change in the header or the body is detected
+        let mut corrupted = bytes.clone();
+        corrupted[20] ^= 1;
+        assert_eq!(load_bytes(&corrupted), Some(HnswError::ChecksumMismatch));
+
+        let mut corrupted = bytes.clone();
+        corrupted[HEADER_SIZE + 100] ^= 1;
+        assert_eq!(load_bytes(&corrupted), Some(HnswError::ChecksumMismatch));
+
+        let mut newer = bytes.clone();
+        newer[8] = 2;
+        assert_eq!(load_bytes(&newer), Some(HnswError::UnsupportedVersion(2)));
+
+        assert!(matches!(
+            load_bytes(&bytes[..bytes.len() - 8]),
+            Some(HnswError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            load_bytes(b"not an index"),
+            Some(HnswError::InvalidFormat(_))
+        ));
+
+        // vectors of another dimension or type
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            load::<f32, 3>(&path).err(),
+            Some(HnswError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            load::<f64, 2>(&path).err(),
+            Some(HnswError::InvalidFormat(_))
+        ));
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            load::<f32, 2>(&path).err(),
+            Some(HnswError::Io(io::ErrorKind::NotFound))
+        );
+    }
+
+    /// Write the checksums of a file modified in place, so that it is rejected for its content only
+    fn reseal(bytes: &mut [u8]) {
+        let body_checksum = Crc32::checksum(&bytes[HEADER_SIZE..]);
+        bytes[128..132].copy_from_slice(&body_checksum.to_le_bytes());
+        let header_checksum = Crc32::checksum(&bytes[..132]);
+        bytes[132..136].copy_from_slice(&header_checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn test_load_inconsistent() {
+        let path = temporary_path("inconsistent");
+        build_index().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let file = IndexFile::parse::<f32>(bytes.as_slice(), 2, true).unwrap();
+        let (ids, levels) = (file.layout.ids, file.layout.levels.clone());
+
+        let load_modified = |values: &[(usize, u64)]| {
+            let mut modified = bytes.clone();
+            for &(offset, value) in values {
+                modified[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            }
+            reseal(&mut modified);
+            fs::write(&path, &modified).unwrap();
+            load::<f32, 2>(&path).err()
+        };
+
+        // a large next id is valid, but an id too large for storage to be allocated for all the ids below it is an
+        // error rather than an abort
+        let last_id = ids + 8 * (file.header.num_nodes - 1);
+        assert_eq!(load_modified(&[(104, 1 << 60)]), None);
+        assert_eq!(
+            load_modified(&[(104, 1 << 60), (last_id, 1 << 59)]),
+            Some(HnswError::Io(io::ErrorKind::OutOfMemory))
+        );
+
+        // an edge of an upper level towards a node that only belongs to the base level
+        let outside = (0..file.header.num_nodes)
+            .find(|&rank| file.position(1, rank).unwrap().is_none())
+            .unwrap();
+        assert_eq!(
+            load_modified(&[(levels[1].edges, outside as u64)]),
+            Some(HnswError::InvalidFormat(
+                "edge towards a node outside of the level"
+            ))
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_sparse() {
+        let path = temporary_path("sparse");
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = Index::<f32, 2>::new(8, 16, euclidean, rng).unwrap();
+
+        let ids = index
+            .insert_batch((0..2000).map(|i| [i as f32; 2]))
+            .unwrap();
+        for &id in &ids[..1998] {
+            index.mark_deleted(id).unwrap();
+        }
+        index.compact(false).unwrap();
+
+        // the rows are written densely and the remaining vectors loaded back with their ids
+        index.save(&path).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < 1024);
+        let loaded = load::<f32, 2>(&path).unwrap();
+        assert_eq!(loaded.nodes, index.nodes);
+        assert_eq!(loaded.levels, index.levels);
+        assert_eq!(loaded.get(ids[1999]), Some(&[1999.0; 2]));
+
+        // a file is replaced once the new one is complete, without leaving the temporary file behind
+        index.compact(true).unwrap();
+        index.save(&path).unwrap();
+        let loaded = load::<f32, 2>(&path).unwrap();
+        assert_eq!(loaded.get(0), Some(&[1998.0; 2]));
+        assert_eq!(loaded.next_id, 2);
+        assert!(!path.with_extension("bin.tmp").exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
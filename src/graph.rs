@@ -0,0 +1,444 @@
+use crate::{builder::Parameters, error::HnswError, hnsw::NeighborSelection, level::Level};
+use rand::Rng;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashSet},
+};
+
+/// Utility struct to be used with a binary heap in the neighbor search. Its id stays a `usize` for indexes storing ids
+/// on 32 bits in their levels: the alignment of the distance pads a candidate to 16 bytes with either id, and the
+/// candidates and visited sets only live for the duration of a search, so only the edges are worth compacting
+#[derive(Debug, PartialEq)]
+pub(crate) struct Candidate {
+    pub id: usize,
+    pub distance: f64,
+}
+
+impl Candidate {
+    pub fn new(id: usize, distance: f64) -> Self {
+        Self { id, distance }
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance)
+    }
+}
+
+/// Read access to the vectors and the levels of an index, whatever the way they are stored, so that the traversal and
+/// the neighbor selection are shared by all the indexes
+pub(crate) trait Graph<T> {
+    /// Return the vector stored for a given node id
+    fn vector(&self, id: usize) -> Result<&[T], HnswError>;
+
+    /// Compute the distance between two vectors with the distance metric of the index
+    fn metric(&self, x: &[T], y: &[T]) -> f64;
+
+    /// Call `visit` with the id of each neighbor of a node in a level, nothing is visited if the node doesn't belong to
+    /// the level
+    fn for_each_neighbor(
+        &self,
+        level_index: usize,
+        node_id: usize,
+        visit: impl FnMut(usize) -> Result<(), HnswError>,
+    ) -> Result<(), HnswError>;
+
+    /// Return the construction parameters of the index
+    fn parameters(&self) -> Parameters;
+
+    /// Return the maximum number of connections of a node in a level
+    fn max_connections(&self, level_index: usize) -> usize {
+        let parameters = self.parameters();
+
+        if level_index > 0 {
+            parameters.max_connections
+        } else {
+            parameters.max_connections_0
+        }
+    }
+
+    /// Compute the distance between two vectors, rejecting NaN values as they cannot be ordered
+    fn distance(&self, x: &[T], y: &[T]) -> Result<f64, HnswError> {
+        let distance = self.metric(x, y);
+
+        if distance.is_nan() {
+            Err(HnswError::InvalidDistance)
+        } else {
+            Ok(distance)
+        }
+    }
+}
+
+/// Write access to the levels of an index built by inserting one vector at a time, so that the insertion of nodes and
+/// the pruning of their edges are shared by the indexes
+pub(crate) trait GraphMut<T>: Graph<T> + Sized {
+    fn levels(&self) -> &[Level];
+
+    fn levels_mut(&mut self) -> &mut Vec<Level>;
+
+    /// Return the node of the top level where every traversal starts
+    fn entry_point(&self) -> Option<usize>;
+
+    fn set_entry_point(&mut self, id: usize);
+
+    /// Sample the highest level of a new node, see `sample_level_index`
+    fn sample_max_level_index(&mut self) -> usize;
+}
+
+/// Define the highest level of a node by sampling from an exponentially decaying distribution
+pub(crate) fn sample_level_index(rng: &mut impl Rng, level_multiplier: f64) -> usize {
+    let log_p = rng.gen_range::<f64, _>(f64::EPSILON..=1.0).ln();
+
+    (-log_p * level_multiplier).floor() as usize
+}
+
+/// Travel the levels from `top_level_index` down to the one above `bottom_level_index`, moving from the entry point to
+/// the node closest to the query in each of them, and return the nodes to enter `bottom_level_index` from
+pub(crate) fn descend<T, G: Graph<T>>(
+    graph: &G,
+    query: &[T],
+    entry_id: usize,
+    top_level_index: usize,
+    bottom_level_index: usize,
+) -> Result<Vec<usize>, HnswError> {
+    let mut entry_ids = Vec::from([entry_id]);
+
+    for level_index in (bottom_level_index + 1..=top_level_index).rev() {
+        entry_ids = search_level(graph, level_index, query, &entry_ids, 1, |_| true)?
+            .into_iter()
+            .map(|candidate| candidate.id)
+            .collect();
+    }
+
+    Ok(entry_ids)
+}
+
+/// Search for the nearest neighbors of the query in the base level with a dynamic candidate list of size `ef`, after
+/// traveling the levels above from the entry point of the top level. Nodes for which `admit` returns false are
+/// traversed but not returned
+pub(crate) fn search<T, G: Graph<T>>(
+    graph: &G,
+    query: &[T],
+    entry_id: usize,
+    top_level_index: usize,
+    ef: usize,
+    admit: impl Fn(usize) -> bool,
+) -> Result<Vec<Candidate>, HnswError> {
+    let entry_ids = descend(graph, query, entry_id, top_level_index, 0)?;
+
+    search_level(graph, 0, query, &entry_ids, ef, admit)
+}
+
+/// Perform BFS in a level from a starting set of nodes, and return the nearest `ef` closest neighbors found among the
+/// nodes for which `admit` returns true. Other nodes are still traversed so that they keep routing the search
+pub(crate) fn search_level<T, G: Graph<T>>(
+    graph: &G,
+    level_index: usize,
+    query: &[T],
+    entry_ids: &[usize],
+    ef: usize,
+    admit: impl Fn(usize) -> bool,
+) -> Result<Vec<Candidate>, HnswError> {
+    let max_connections = graph.max_connections(level_index);
+    let mut candidates = BinaryHeap::with_capacity(max_connections); // min heap
+    let mut nearest_neighbors = BinaryHeap::with_capacity(ef); // max heap
+    let mut visited = HashSet::new();
+
+    for &entry_id in entry_ids {
+        let distance = graph.distance(query, graph.vector(entry_id)?)?;
+
+        visited.insert(entry_id);
+        candidates.push(Reverse(Candidate::new(entry_id, distance)));
+
+        if admit(entry_id) {
+            nearest_neighbors.push(Candidate::new(entry_id, distance));
+        }
+    }
+
+    while let Some(closest) = candidates.pop().map(|c| c.0) {
+        let furthest_distance = nearest_neighbors
+            .peek()
+            .map_or(f64::INFINITY, |c| c.distance);
+
+        // all closest neighbors have been explored
+        if nearest_neighbors.len() >= ef && closest.distance > furthest_distance {
+            break;
+        }
+
+        graph.for_each_neighbor(level_index, closest.id, |neighbor_id| {
+            if !visited.insert(neighbor_id) {
+                return Ok(());
+            }
+
+            let distance = graph.distance(query, graph.vector(neighbor_id)?)?;
+
+            if nearest_neighbors.len() < ef || distance < furthest_distance {
+                candidates.push(Reverse(Candidate::new(neighbor_id, distance)));
+
+                if admit(neighbor_id) {
+                    nearest_neighbors.push(Candidate::new(neighbor_id, distance));
+
+                    if nearest_neighbors.len() > ef {
+                        nearest_neighbors.pop();
+                    }
+                }
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(nearest_neighbors.into_sorted_vec())
+}
+
+/// Connect a node already stored in the index to its nearest neighbors in each level up to a sampled one, traveling
+/// the levels above from the entry point. A node sampled above the top level becomes the new entry point
+pub(crate) fn insert_node<T, G: GraphMut<T>>(
+    graph: &mut G,
+    node_id: usize,
+    vector: &[T],
+) -> Result<(), HnswError> {
+    let Some(entry_id) = graph.entry_point() else {
+        insert_level_then_node(graph, node_id);
+        return Ok(());
+    };
+
+    let parameters = graph.parameters();
+    let top_level_index = graph.levels().len() - 1;
+    let mut max_level_index = graph.sample_max_level_index();
+
+    // handle the case of sampling a level higher than the current top level, the node then becomes the new entry
+    // point but the traversal below still starts from the previous one
+    if max_level_index > top_level_index {
+        insert_level_then_node(graph, node_id);
+        max_level_index = top_level_index;
+    }
+
+    // travel hierarchy for levels above the highest level of this node
+    let mut entry_ids = descend(graph, vector, entry_id, top_level_index, max_level_index)?;
+
+    // travel hierarchy for levels equal or below the highest level of this node
+    for level_index in (0..=max_level_index).rev() {
+        // add the node to the level
+        graph.levels_mut()[level_index].insert(node_id);
+
+        // look for neighbors to connect
+        let candidates = search_level(
+            graph,
+            level_index,
+            vector,
+            &entry_ids,
+            parameters.ef_construction,
+            |_| true,
+        )?;
+
+        entry_ids = candidates.iter().map(|candidate| candidate.id).collect();
+
+        let neighbors = select_neighbors(
+            graph,
+            level_index,
+            node_id,
+            candidates,
+            parameters.connections,
+            parameters.neighbor_selection,
+        )?;
+        connect_neighbors(graph, level_index, node_id, &neighbors)?;
+        prune_connections(graph, level_index, &neighbors)?;
+    }
+
+    Ok(())
+}
+
+/// Create a new top level containing only the node, which becomes the entry point of the index
+fn insert_level_then_node<T, G: GraphMut<T>>(graph: &mut G, id: usize) {
+    let level_index = graph.levels().len();
+    let compact_ids = graph.parameters().compact_ids;
+    let mut level = Level::new(
+        graph.max_connections(level_index),
+        level_index == 0,
+        compact_ids,
+    );
+    level.insert(id);
+    graph.levels_mut().push(level);
+    graph.set_entry_point(id);
+}
+
+/// Create a bidirectional edge between a node id and a set of neighbors, in a given level
+pub(crate) fn connect_neighbors<T, G: GraphMut<T>>(
+    graph: &mut G,
+    level_index: usize,
+    node_id: usize,
+    neighbors: &[Candidate],
+) -> Result<(), HnswError> {
+    let level = &mut graph.levels_mut()[level_index];
+
+    for &Candidate { id, .. } in neighbors {
+        for (from, to) in [(node_id, id), (id, node_id)] {
+            let connected = level
+                .get(from)
+                .ok_or(HnswError::NodeNotFound(from))?
+                .any(|neighbor_id| neighbor_id == to);
+
+            // an updated node can already be connected to some of its new neighbors
+            if !connected {
+                level.push(from, to);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Shrink the edges of each neighbor having more than the maximum number of connections allowed in the level
+pub(crate) fn prune_connections<T, G: GraphMut<T>>(
+    graph: &mut G,
+    level_index: usize,
+    neighbors: &[Candidate],
+) -> Result<(), HnswError> {
+    // special case for the base level as described in the paper, they recommend to set it to 2M
+    let max_connections = graph.max_connections(level_index);
+    let pruning = graph.parameters().pruning;
+
+    for &Candidate { id, .. } in neighbors {
+        let Some(edges) = graph.levels()[level_index].get(id) else {
+            continue;
+        };
+        if edges.len() <= max_connections {
+            continue;
+        }
+
+        // sort edges by the distances to node `id`
+        let query = graph.vector(id)?;
+        let mut candidates = edges
+            .map(|neighbor_id| {
+                let distance = graph.distance(query, graph.vector(neighbor_id)?)?;
+                Ok(Candidate::new(neighbor_id, distance))
+            })
+            .collect::<Result<Vec<_>, HnswError>>()?;
+        candidates.sort_unstable();
+
+        // keep at most `max_connections` edges, either the closest ones or the most diverse ones
+        let kept = select_neighbors(graph, level_index, id, candidates, max_connections, pruning)?;
+
+        graph.levels_mut()[level_index]
+            .set(id, kept.iter().map(|c| c.id))
+            .ok_or(HnswError::NodeNotFound(id))?;
+    }
+
+    Ok(())
+}
+
+/// Select at most `k` neighbors for a node among candidates sorted by increasing distance to it
+pub(crate) fn select_neighbors<T, G: Graph<T>>(
+    graph: &G,
+    level_index: usize,
+    node_id: usize,
+    candidates: Vec<Candidate>,
+    k: usize,
+    strategy: NeighborSelection,
+) -> Result<Vec<Candidate>, HnswError> {
+    match strategy {
+        NeighborSelection::Simple => Ok(candidates.into_iter().take(k).collect()),
+        NeighborSelection::Heuristic {
+            extend_candidates,
+            keep_pruned_connections,
+        } => select_neighbors_heuristic(
+            graph,
+            level_index,
+            node_id,
+            candidates,
+            k,
+            extend_candidates,
+            keep_pruned_connections,
+        ),
+    }
+}
+
+/// Select neighbors using the heuristic described in the paper: a candidate is only kept if it is closer to the node
+/// than to every neighbor selected so far
+fn select_neighbors_heuristic<T, G: Graph<T>>(
+    graph: &G,
+    level_index: usize,
+    node_id: usize,
+    mut candidates: Vec<Candidate>,
+    k: usize,
+    extend_candidates: bool,
+    keep_pruned_connections: bool,
+) -> Result<Vec<Candidate>, HnswError> {
+    if extend_candidates {
+        let query = graph.vector(node_id)?;
+        let mut seen = candidates.iter().map(|c| c.id).collect::<HashSet<_>>();
+        seen.insert(node_id);
+
+        let mut extension = Vec::new();
+        for candidate in &candidates {
+            graph.for_each_neighbor(level_index, candidate.id, |neighbor_id| {
+                if seen.insert(neighbor_id) {
+                    let distance = graph.distance(query, graph.vector(neighbor_id)?)?;
+                    extension.push(Candidate::new(neighbor_id, distance));
+                }
+
+                Ok(())
+            })?;
+        }
+
+        candidates.extend(extension);
+        candidates.sort_unstable();
+    }
+
+    let mut selected: Vec<Candidate> = Vec::with_capacity(k);
+    let mut discarded = Vec::new();
+
+    for candidate in candidates {
+        if selected.len() >= k {
+            break;
+        }
+
+        let vector = graph.vector(candidate.id)?;
+        let mut is_diverse = true;
+
+        // ties are kept, otherwise a node would only be connected to an exact duplicate of itself
+        for neighbor in &selected {
+            if graph.distance(vector, graph.vector(neighbor.id)?)? < candidate.distance {
+                is_diverse = false;
+                break;
+            }
+        }
+
+        if is_diverse {
+            selected.push(candidate);
+        } else {
+            discarded.push(candidate);
+        }
+    }
+
+    if keep_pruned_connections {
+        let remaining = k - selected.len();
+        selected.extend(discarded.into_iter().take(remaining));
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Candidate;
+    use std::mem;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_candidate_size() {
+        // the distance pads a candidate to 16 bytes, a 32-bit id would not make it smaller
+        assert_eq!(mem::size_of::<Candidate>(), 16);
+        assert_eq!(mem::size_of::<(u32, f64)>(), 16);
+    }
+}
@@ -1,62 +1,91 @@
-use rand::{seq::IteratorRandom, Rng};
+use crate::{
+    arena::Vectors,
+    builder::{self, HnswBuilder, Parameters},
+    error::HnswError,
+    graph::{self, Candidate, Graph, GraphMut},
+    level::{self, Level},
+};
+use rand::Rng;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::{
-    cmp::{min, Ordering, Reverse},
+    cmp::Reverse,
     collections::{BinaryHeap, HashMap, HashSet},
     fmt::Debug,
 };
 
-type Nodes<T, const D: usize> = HashMap<usize, [T; D]>;
-type Level = HashMap<usize, Vec<usize>>;
-
-/// Utility struct to be used with a binary heap in the neighbor search
-#[derive(Debug, PartialEq)]
-struct Candidate {
-    pub id: usize,
-    pub distance: f64,
-}
-
-impl Candidate {
-    pub fn new(id: usize, distance: f64) -> Self {
-        Self { id, distance }
-    }
-}
-
-impl Eq for Candidate {}
-
-impl PartialOrd for Candidate {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+/// Results of one of the queries of a batch
+pub type BatchResult<'v, T, const D: usize> = Result<Vec<SearchResult<'v, T, D>>, HnswError>;
+
+/// Strategy used to choose which candidates a node gets connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NeighborSelection {
+    /// Connect to the closest candidates
+    Simple,
+    /// Connect to candidates that are closer to the node than to any neighbor already selected (algorithm 4 of the
+    /// paper), which keeps edges towards other clusters. `extend_candidates` adds the neighbors of the candidates to the
+    /// pool before selecting, and `keep_pruned_connections` fills the remaining slots with the closest discarded ones
+    Heuristic {
+        extend_candidates: bool,
+        keep_pruned_connections: bool,
+    },
 }
 
-impl Ord for Candidate {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.distance.partial_cmp(&other.distance).unwrap()
+impl Default for NeighborSelection {
+    fn default() -> Self {
+        Self::Heuristic {
+            extend_candidates: false,
+            keep_pruned_connections: false,
+        }
     }
 }
 
 /// Utility struct to store a nearest neighbor search result
 #[derive(Debug)]
 pub struct SearchResult<'v, T, const D: usize> {
+    pub id: usize,
     pub vector: &'v [T; D],
     pub distance: f64,
 }
 
 impl<'v, T, const D: usize> SearchResult<'v, T, D> {
-    pub fn new(vector: &'v [T; D], distance: f64) -> Self {
-        Self { vector, distance }
+    pub fn new(id: usize, vector: &'v [T; D], distance: f64) -> Self {
+        Self {
+            id,
+            vector,
+            distance,
+        }
     }
 }
 
+/// Counts of the vectors stored in the index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IndexStats {
+    pub num_live: usize,
+    pub num_deleted: usize,
+    pub num_levels: usize,
+}
+
 pub struct HNSW<T, const D: usize, F, R> {
-    connections: usize, // M parameter
+    connections: usize,    // M parameter
+    level_multiplier: f64, // m_L parameter
     ef_construction: usize,
+    ef_search: usize,
+    neighbor_selection: NeighborSelection,
+    pruning: NeighborSelection,
+    compact_ids: bool,
     distance_metric: F,
     rng: R,
     pub(crate) max_connections: usize,   // Mmax parameter
     pub(crate) max_connections_0: usize, // Mmax0
-    pub(super) nodes: Nodes<T, D>,
+    pub(super) nodes: Vectors<T, D>,
     pub(super) levels: Vec<Level>,
+    pub(super) entry_point: Option<usize>, // node in the top level where every traversal starts
+    pub(super) deleted: HashSet<usize>,    // tombstoned nodes, kept in the graph for routing only
     pub(super) next_id: usize,
 }
 
@@ -66,174 +95,253 @@ where
     F: Fn(&[T], &[T]) -> f64,
     R: Rng,
 {
-    pub fn new(connections: usize, ef_construction: usize, distance_metric: F, rng: R) -> Self {
-        // heuristic to bound the connectivity of the levels
-        let max_connections = (1.5 * (connections as f32)).round() as usize;
-        let max_connections_0 = 2 * connections;
+    /// Create an empty index using the parameters recommended in the paper for the given `M` and `ef_construction`.
+    /// Use `HnswBuilder` to set the other parameters
+    pub fn new(
+        connections: usize,
+        ef_construction: usize,
+        distance_metric: F,
+        rng: R,
+    ) -> Result<Self, HnswError> {
+        HnswBuilder::new(connections)
+            .ef_construction(ef_construction)
+            .build_with_rng(distance_metric, rng)
+    }
 
-        let nodes = Nodes::new();
+    pub(crate) fn from_parameters(parameters: Parameters, distance_metric: F, rng: R) -> Self {
+        let nodes = Vectors::new();
         let levels = Vec::new();
+        let entry_point = None;
+        let deleted = HashSet::new();
         let next_id = 0;
 
         Self {
-            connections,
-            ef_construction,
+            connections: parameters.connections,
+            level_multiplier: parameters.level_multiplier,
+            ef_construction: parameters.ef_construction,
+            ef_search: parameters.ef_search,
+            neighbor_selection: parameters.neighbor_selection,
+            pruning: parameters.pruning,
+            compact_ids: parameters.compact_ids,
             distance_metric,
             rng,
-            max_connections,
-            max_connections_0,
+            max_connections: parameters.max_connections,
+            max_connections_0: parameters.max_connections_0,
             nodes,
             levels,
+            entry_point,
+            deleted,
             next_id,
         }
     }
 
-    /// Define the highest level by sampling from an exponentially decaying distribution
-    fn sample_max_level_index(&mut self) -> usize {
-        let level_multiplier = 1.0 / (self.connections as f64).ln();
-        let log_p = self.rng.gen_range::<f64, _>(f64::EPSILON..=1.0).ln();
-
-        -(log_p * level_multiplier).floor() as usize - 1
+    /// Return the construction parameters of the index
+    pub(crate) fn parameters(&self) -> Parameters {
+        Parameters {
+            connections: self.connections,
+            max_connections: self.max_connections,
+            max_connections_0: self.max_connections_0,
+            level_multiplier: self.level_multiplier,
+            ef_construction: self.ef_construction,
+            ef_search: self.ef_search,
+            neighbor_selection: self.neighbor_selection,
+            pruning: self.pruning,
+            compact_ids: self.compact_ids,
+        }
     }
 
-    /// Randomly sample a node in the top layer. We are guaranteed to have at least one point when invoking this method
-    fn sample_entry_id(&mut self, level_index: usize) -> usize {
-        *self.levels[level_index]
-            .keys()
-            .choose(&mut self.rng)
-            .unwrap()
+    /// Set the size of the dynamic candidate list used when searching the base level, which must be at least 1
+    pub fn with_ef_search(mut self, ef_search: usize) -> Result<Self, HnswError> {
+        self.ef_search = builder::check_ef_search(ef_search)?;
+        Ok(self)
     }
 
-    /// Insert a new vector in the index and return its unique id
-    fn insert_vector(&mut self, vector: &[T; D]) -> usize {
+    /// Insert a new vector in the index and return its unique id, or an error if no id is left
+    fn insert_vector(&mut self, vector: &[T; D]) -> Result<usize, HnswError> {
         let id = self.next_id;
+        level::check_id(id, self.compact_ids)?;
         self.nodes.insert(id, *vector);
         self.next_id += 1;
 
-        id
+        Ok(id)
     }
 
-    // TODO: implement heuristic as described in the paper
-    fn select_neighbors<'c>(&self, candidates: &'c [Candidate], k: usize) -> &'c [Candidate] {
-        &candidates[..=min(k, candidates.len() - 1)]
+    /// Return the vector stored for a given node id
+    fn get_vector(&self, id: usize) -> Result<&[T; D], HnswError> {
+        self.nodes.get(id).ok_or(HnswError::NodeNotFound(id))
     }
 
     /// Returns all the indices of neighboring nodes of a given node id and level index, if they exist
-    fn get_neighbors(&self, level_index: usize, node_id: usize) -> Option<&Vec<usize>> {
-        self.levels[level_index].get(&node_id)
+    fn get_neighbors(
+        &self,
+        level_index: usize,
+        node_id: usize,
+    ) -> Option<impl ExactSizeIterator<Item = usize> + Clone + '_> {
+        self.levels[level_index].get(node_id)
     }
 
-    /// Create a bidirectional edge between a node id and a set of neighbors, in a given level
-    fn connect_neighbors(&mut self, level_index: usize, node_id: usize, neighbors: &[Candidate]) {
-        for &Candidate { id, .. } in neighbors {
-            self.levels[level_index].get_mut(&node_id).unwrap().push(id);
-            self.levels[level_index].get_mut(&id).unwrap().push(node_id);
-        }
+    /// Create a level without nodes whose slots hold the maximum number of connections allowed in the given level,
+    /// indexed by id for the base level
+    pub(crate) fn empty_level(&self, level_index: usize) -> Level {
+        Level::new(
+            self.max_connections(level_index),
+            level_index == 0,
+            self.compact_ids,
+        )
     }
 
-    /// Perform BFS in a level from a starting set of nodes, and return the nearest `ef` closest neighbors found
-    fn search_level(
+    /// Select new edges for a node among its two-hop neighborhood, extended with `extra_ids` and leaving out the nodes
+    /// that no longer belong to the level as well as the `removed_ids` about to be removed from it. Used to reconnect
+    /// the neighbors of nodes being removed or updated
+    fn select_repaired_edges(
         &self,
         level_index: usize,
-        query: &[T; D],
-        entry_ids: &[usize],
-        ef: usize,
-    ) -> Vec<Candidate> {
-        let max_connections = self.get_max_connections(level_index);
-        let mut candidates = BinaryHeap::with_capacity(max_connections); // min heap
-        let mut nearest_neighbors = BinaryHeap::with_capacity(ef); // max heap
-        let mut visited = HashSet::new();
-
-        for &entry_id in entry_ids {
-            let distance = (self.distance_metric)(query, self.nodes.get(&entry_id).unwrap());
-
-            visited.insert(entry_id);
-            candidates.push(Reverse(Candidate::new(entry_id, distance)));
-            nearest_neighbors.push(Candidate::new(entry_id, distance));
-        }
-
-        while let Some(closest) = candidates.pop().map(|c| c.0) {
-            let furthest_distance = nearest_neighbors.peek().map(|c| c.distance).unwrap();
-
-            // all closest neighbors have been explored
-            if closest.distance > furthest_distance {
-                break;
+        node_id: usize,
+        extra_ids: &[usize],
+        removed_ids: &HashSet<usize>,
+    ) -> Result<Vec<usize>, HnswError> {
+        let level = &self.levels[level_index];
+        let edges = level.get(node_id).ok_or(HnswError::NodeNotFound(node_id))?;
+
+        let mut candidate_ids = HashSet::new();
+        for neighbor_id in edges.chain(extra_ids.iter().copied()) {
+            candidate_ids.insert(neighbor_id);
+
+            if removed_ids.contains(&neighbor_id) {
+                continue;
             }
-
-            if let Some(neighbor_ids) = self.get_neighbors(level_index, closest.id) {
-                neighbor_ids
-                    .iter()
-                    .filter(|&&neighbor_id| visited.insert(neighbor_id))
-                    .for_each(|&neighbor_id| {
-                        let distance =
-                            (self.distance_metric)(query, self.nodes.get(&neighbor_id).unwrap());
-
-                        if nearest_neighbors.len() < ef || distance < furthest_distance {
-                            candidates.push(Reverse(Candidate::new(neighbor_id, distance)));
-                            nearest_neighbors.push(Candidate::new(neighbor_id, distance));
-
-                            if nearest_neighbors.len() > ef {
-                                nearest_neighbors.pop();
-                            }
-                        }
-                    })
+            if let Some(second_neighbor_ids) = level.get(neighbor_id) {
+                candidate_ids.extend(second_neighbor_ids);
             }
         }
-
-        nearest_neighbors.into_sorted_vec()
+        candidate_ids.remove(&node_id);
+        candidate_ids.retain(|candidate_id| {
+            level.contains(*candidate_id) && !removed_ids.contains(candidate_id)
+        });
+
+        let query = self.get_vector(node_id)?;
+        let mut candidates = candidate_ids
+            .into_iter()
+            .map(|candidate_id| {
+                let distance = self.distance(query, self.get_vector(candidate_id)?)?;
+                Ok(Candidate::new(candidate_id, distance))
+            })
+            .collect::<Result<Vec<_>, HnswError>>()?;
+        candidates.sort_unstable();
+
+        let max_connections = self.max_connections(level_index);
+        let kept = graph::select_neighbors(
+            self,
+            level_index,
+            node_id,
+            candidates,
+            max_connections,
+            self.pruning,
+        )?;
+
+        // extending the candidates can reach the removed nodes through the edges not repaired yet
+        Ok(kept
+            .into_iter()
+            .map(|candidate| candidate.id)
+            .filter(|id| !removed_ids.contains(id))
+            .collect())
     }
 
-    fn insert_level_then_node(&mut self, id: usize, max_connections: usize) {
-        let level = Level::from([(id, Vec::with_capacity(max_connections))]);
-        self.levels.push(level);
+    /// Replace the edges of a node by the ones selected by `select_repaired_edges`
+    fn repair_connections(&mut self, level_index: usize, node_id: usize) -> Result<(), HnswError> {
+        let edges = self.select_repaired_edges(level_index, node_id, &[], &HashSet::new())?;
+        self.levels[level_index]
+            .set(node_id, edges)
+            .ok_or(HnswError::NodeNotFound(node_id))
     }
 
-    fn get_max_connections(&self, level_index: usize) -> usize {
-        if level_index > 0 {
-            self.max_connections
-        } else {
-            self.max_connections_0
+    /// Remove nodes from a level and reconnect each node that had an edge towards them through their neighbors. The
+    /// new edges are all selected before the level is modified, so that it is left unchanged if one of them fails
+    fn remove_from_level(
+        &mut self,
+        level_index: usize,
+        removed_ids: &HashSet<usize>,
+    ) -> Result<(), HnswError> {
+        let level = &self.levels[level_index];
+        let removed_edges = removed_ids
+            .iter()
+            .filter_map(|&id| Some((id, level.get(id)?.collect::<Vec<_>>())))
+            .collect::<HashMap<_, _>>();
+
+        if removed_edges.is_empty() {
+            return Ok(());
         }
+
+        // reconnect each node through the neighbors of all the removed nodes it was connected to
+        let orphans = level
+            .iter()
+            .filter(|(node_id, edges)| {
+                !removed_ids.contains(node_id) && edges.clone().any(|id| removed_ids.contains(&id))
+            })
+            .map(|(node_id, edges)| {
+                let extra_ids = edges
+                    .filter_map(|id| removed_edges.get(&id))
+                    .flatten()
+                    .copied()
+                    .collect::<Vec<_>>();
+
+                (node_id, extra_ids)
+            })
+            .collect::<Vec<_>>();
+
+        let repaired_edges = orphans
+            .into_iter()
+            .map(|(node_id, extra_ids)| {
+                let edges =
+                    self.select_repaired_edges(level_index, node_id, &extra_ids, removed_ids)?;
+                Ok((node_id, edges))
+            })
+            .collect::<Result<Vec<_>, HnswError>>()?;
+
+        let level = &mut self.levels[level_index];
+        for id in removed_edges.keys() {
+            level.remove(*id);
+        }
+        for (node_id, edges) in repaired_edges {
+            level.set(node_id, edges);
+        }
+
+        Ok(())
     }
 
-    fn prune_connections(&mut self, level_index: usize, neighbors: &[Candidate]) {
-        // special case for the base level as described in the paper, they recommend to set it to 2M
-        let max_connections = self.get_max_connections(level_index);
-
-        for Candidate { id, .. } in neighbors {
-            if let Some(edges) = self.levels[level_index].get_mut(id) {
-                if edges.len() > max_connections {
-                    // sort edges by the distances to node `id`
-                    let query = self.nodes.get(id).unwrap();
-                    let distances = edges
-                        .iter()
-                        .map(|&neighbor_id| {
-                            Reverse(Candidate::new(
-                                neighbor_id,
-                                (self.distance_metric)(
-                                    query,
-                                    self.nodes.get(&neighbor_id).unwrap(),
-                                ),
-                            ))
-                        })
-                        .collect::<BinaryHeap<_>>()
-                        .into_sorted_vec();
-
-                    // prune connections to farthest nodes keeping only the `max_connections` closest
-                    edges.clear();
-                    edges.extend(distances.iter().take(max_connections).map(|c| c.0.id));
-                }
-            }
+    /// Remove nodes from every level and then from the index. Levels are processed from the top one, so that after a
+    /// failure the nodes left in a level still belong to the levels below, and the entry point is elected again
+    /// whatever the outcome
+    fn remove_nodes(&mut self, ids: &HashSet<usize>) -> Result<(), HnswError> {
+        let result = (0..self.num_levels())
+            .rev()
+            .try_for_each(|level_index| self.remove_from_level(level_index, ids));
+        self.drop_empty_levels();
+        result?;
+
+        for &id in ids {
+            self.nodes.remove(id);
         }
+
+        Ok(())
     }
 
     pub fn is_empty(&self) -> bool {
-        self.nodes.is_empty()
+        self.len() == 0
     }
 
-    /// Return the number of vectors stored in the index
+    /// Return the number of vectors stored in the index, excluding the ones marked as deleted
     pub fn len(&self) -> usize {
-        self.nodes.len()
+        self.nodes.len() - self.deleted.len()
+    }
+
+    /// Return the number of live and deleted vectors as well as the number of levels
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            num_live: self.len(),
+            num_deleted: self.deleted.len(),
+            num_levels: self.num_levels(),
+        }
     }
 
     /// Return the number of levels in the index
@@ -241,94 +349,416 @@ where
         self.levels.len()
     }
 
-    /// Insert a new vector in the index
-    pub fn insert(&mut self, vector: &[T; D]) {
-        let node_id = self.insert_vector(vector);
+    /// Return the vector stored with the given id, if any
+    pub fn get(&self, id: usize) -> Option<&[T; D]> {
+        self.nodes.get(id)
+    }
 
-        if self.levels.is_empty() {
-            self.insert_level_then_node(node_id, self.max_connections_0);
-        } else {
-            let top_level_index = self.num_levels() - 1;
-            let mut max_level_index = self.sample_max_level_index();
+    /// Return true if a vector is stored with the given id, even if it is marked as deleted
+    pub fn contains(&self, id: usize) -> bool {
+        self.nodes.contains(id)
+    }
 
-            // handle the case of sampling a level higher than the current top level
-            if max_level_index > top_level_index {
-                self.insert_level_then_node(node_id, self.max_connections);
-                max_level_index = top_level_index;
-            }
+    /// Return true if the vector stored with the given id is marked as deleted
+    pub fn is_deleted(&self, id: usize) -> bool {
+        self.deleted.contains(&id)
+    }
 
-            // sample entry point
-            let mut entry_ids = Vec::from([self.sample_entry_id(top_level_index)]);
+    /// Mark a vector as deleted: it stays in the graph to route searches but is no longer returned by them
+    pub fn mark_deleted(&mut self, id: usize) -> Result<(), HnswError> {
+        if !self.contains(id) {
+            return Err(HnswError::NodeNotFound(id));
+        }
 
-            // travel hierarchy for levels above the highest level of this node
-            for level_index in (max_level_index + 1..=top_level_index).rev() {
-                entry_ids = self
-                    .search_level(level_index, vector, &entry_ids, 1)
-                    .into_iter()
-                    .map(|candidate| candidate.id)
-                    .collect();
-            }
+        self.deleted.insert(id);
+
+        Ok(())
+    }
+
+    /// Remove a vector from the index and from every level it belongs to. Nodes that had an edge towards it are
+    /// reconnected to their two-hop neighborhood, and if it was the entry point, a new one is elected in the top level
+    /// after dropping the levels left empty. Edges only go one way, so finding these nodes scans the edges of every node
+    /// of its levels, O(N * Mmax0) for the base level: to remove many vectors, mark them as deleted and `compact` the
+    /// index, which scans each level once for all of them. Vectors are stored by id, so the memory of the vector is only
+    /// released if no larger id is stored, or once the ids are renumbered by `compact(true)`
+    pub fn remove(&mut self, id: usize) -> Result<(), HnswError> {
+        if !self.contains(id) {
+            return Err(HnswError::NodeNotFound(id));
+        }
+
+        self.remove_nodes(&HashSet::from([id]))?;
+        self.deleted.remove(&id);
+
+        Ok(())
+    }
+
+    /// Remove every vector marked as deleted from the index, reconnecting the nodes that had an edge towards them as
+    /// `remove` does. When `renumber` is true, the remaining vectors are given dense ids starting from 0, in the order
+    /// of their previous ids, which releases the memory of every removed vector. Return the id of each remaining vector
+    /// indexed by its previous id
+    pub fn compact(&mut self, renumber: bool) -> Result<HashMap<usize, usize>, HnswError> {
+        // the tombstones are only cleared once every level is repaired, so that the vectors still stored after a
+        // failure are kept out of the results
+        let deleted = self.deleted.clone();
+        self.remove_nodes(&deleted)?;
+        self.nodes.shrink_to_fit();
+        self.deleted.clear();
+
+        if !renumber {
+            return Ok(self.nodes.ids().map(|id| (id, id)).collect());
+        }
 
-            // travel hierarchy for levels equal or below the highest level of this node
-            for level_index in (0..=max_level_index).rev() {
-                // add the node to the level
-                let max_connections = self.get_max_connections(level_index);
-                self.levels[level_index].insert(node_id, Vec::with_capacity(max_connections));
+        let remapping = self
+            .nodes
+            .ids()
+            .enumerate()
+            .map(|(new_id, id)| (id, new_id))
+            .collect::<HashMap<_, _>>();
+        self.renumber(&remapping);
 
-                // look for neighbors to connect
-                let candidates =
-                    self.search_level(level_index, vector, &entry_ids, self.ef_construction);
+        Ok(remapping)
+    }
+
+    /// Replace the id of every node by the one it is mapped to, which must be defined for all of them
+    fn renumber(&mut self, remapping: &HashMap<usize, usize>) {
+        self.nodes = self
+            .nodes
+            .iter()
+            .map(|(id, vector)| (remapping[&id], *vector))
+            .collect();
+
+        for level in &mut self.levels {
+            level.renumber(remapping);
+        }
+
+        self.entry_point = self.entry_point.map(|id| remapping[&id]);
+        self.next_id = self.nodes.len();
+    }
+
+    /// Drop the top levels left empty by removals, and elect a new entry point in the top level if the current one was
+    /// removed
+    fn drop_empty_levels(&mut self) {
+        while self.levels.last().is_some_and(|level| level.is_empty()) {
+            self.levels.pop();
+        }
+
+        let top_level = self.levels.last();
+        if !self
+            .entry_point
+            .is_some_and(|id| top_level.is_some_and(|level| level.contains(id)))
+        {
+            self.entry_point = top_level.and_then(|level| level.ids().min());
+        }
+    }
 
-                let neighbors = self.select_neighbors(&candidates, self.connections);
-                self.connect_neighbors(level_index, node_id, neighbors);
-                self.prune_connections(level_index, neighbors);
+    /// Replace the vector stored with the given id and rebuild its edges on every level it belongs to, keeping its id and
+    /// levels. Its previous neighbors are reconnected to their two-hop neighborhood as the node may have moved away
+    pub fn update(&mut self, id: usize, vector: &[T; D]) -> Result<(), HnswError> {
+        if !self.contains(id) {
+            return Err(HnswError::NodeNotFound(id));
+        }
+        self.distance(vector, vector)?;
+        self.nodes.insert(id, *vector);
+
+        let entry_id = self.entry_point.ok_or(HnswError::NodeNotFound(id))?;
+        let top_level_index = self.num_levels() - 1;
+        let max_level_index = (0..=top_level_index)
+            .rev()
+            .find(|&level_index| self.levels[level_index].contains(id))
+            .ok_or(HnswError::NodeNotFound(id))?;
+
+        // travel hierarchy for levels above the highest level of this node
+        let mut entry_ids =
+            graph::descend(self, vector, entry_id, top_level_index, max_level_index)?;
+
+        // relink the node in each of its levels, looking for neighbors other than itself
+        for level_index in (0..=max_level_index).rev() {
+            let candidates = graph::search_level(
+                self,
+                level_index,
+                vector,
+                &entry_ids,
+                self.ef_construction,
+                |candidate_id| candidate_id != id,
+            )?;
+
+            entry_ids = candidates.iter().map(|candidate| candidate.id).collect();
+            if entry_ids.is_empty() {
+                entry_ids.push(id);
+            }
+
+            let previous_edges = self.levels[level_index]
+                .take(id)
+                .ok_or(HnswError::NodeNotFound(id))?;
+
+            let neighbors = graph::select_neighbors(
+                self,
+                level_index,
+                id,
+                candidates,
+                self.connections,
+                self.neighbor_selection,
+            )?;
+            graph::connect_neighbors(self, level_index, id, &neighbors)?;
+            graph::prune_connections(self, level_index, &neighbors)?;
+
+            for neighbor_id in previous_edges {
+                if !neighbors.iter().any(|c| c.id == neighbor_id) {
+                    self.repair_connections(level_index, neighbor_id)?;
+                }
             }
         }
+
+        Ok(())
     }
 
-    /// Insert each element of an iterator in the index
-    pub fn insert_batch<I: Iterator<Item = [T; D]>>(&mut self, batch: I) {
-        batch.for_each(|ref vector| self.insert(vector));
+    /// Restore a vector previously marked as deleted so that searches return it again
+    pub fn undelete(&mut self, id: usize) -> Result<(), HnswError> {
+        if !self.contains(id) {
+            return Err(HnswError::NodeNotFound(id));
+        }
+
+        self.deleted.remove(&id);
+
+        Ok(())
+    }
+
+    /// Insert a new vector in the index and return the id assigned to it. Vectors for which the distance metric returns
+    /// NaN, such as a zero vector with the cosine distance, are rejected, as well as vectors inserted once the largest id
+    /// has been assigned
+    pub fn insert(&mut self, vector: &[T; D]) -> Result<usize, HnswError> {
+        self.distance(vector, vector)?;
+
+        let node_id = self.insert_vector(vector)?;
+        graph::insert_node(self, node_id, vector)?;
+
+        Ok(node_id)
+    }
+
+    /// Insert each element of an iterator in the index and return their ids in order, stopping at the first vector that
+    /// cannot be inserted
+    pub fn insert_batch<I: Iterator<Item = [T; D]>>(
+        &mut self,
+        batch: I,
+    ) -> Result<Vec<usize>, HnswError> {
+        batch.map(|ref vector| self.insert(vector)).collect()
     }
 
     /// Search for the k nearest neighbors from the query vector by traveling the index
     pub fn search(
-        &mut self,
+        &self,
         query: &[T; D],
         k: usize,
-    ) -> Result<Vec<SearchResult<'_, T, D>>, &'static str> {
-        if self.is_empty() {
-            Err("index is empty")
-        } else {
-            // sample a random node in the top layer to start the search from
-            let top_level_index = self.num_levels() - 1;
-            let mut entry_ids = Vec::from([self.sample_entry_id(top_level_index)]);
-
-            // travel the hierarchy from top to bottom by finding the closest entry point for the next level
-            // by construction, we are guaranteed that the node found is also present in all the lower levels
-            for level_index in (1..self.num_levels()).rev() {
-                entry_ids = self
-                    .search_level(level_index, query, &entry_ids, 1)
-                    .into_iter()
-                    .map(|candidate| candidate.id)
-                    .collect();
+    ) -> Result<Vec<SearchResult<'_, T, D>>, HnswError> {
+        self.search_with_ef(query, k, self.ef_search)
+    }
+
+    /// Search for the k nearest neighbors from the query vector, exploring the base level with a dynamic candidate list
+    /// of size `ef` instead of the one set for the index, raised to `k` if smaller. The larger `ef`, the better the recall
+    /// and the slower the search
+    pub fn search_with_ef(
+        &self,
+        query: &[T; D],
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<SearchResult<'_, T, D>>, HnswError> {
+        self.search_admitting(query, k, ef, |_| true)
+    }
+
+    /// Search for the k nearest neighbors of each query vector, and return their results in the same order. A query that
+    /// fails doesn't prevent the others from being answered
+    pub fn search_batch(&self, queries: &[[T; D]], k: usize) -> Vec<BatchResult<'_, T, D>> {
+        queries.iter().map(|query| self.search(query, k)).collect()
+    }
+
+    /// Search for the k nearest neighbors from the query vector among the vectors whose id passes the filter. Vectors
+    /// filtered out are still traversed to reach the other ones, so the recall does not drop as when discarding them
+    /// from the results of `search`
+    pub fn search_filtered(
+        &self,
+        query: &[T; D],
+        k: usize,
+        filter: impl Fn(usize) -> bool,
+    ) -> Result<Vec<SearchResult<'_, T, D>>, HnswError> {
+        self.search_admitting(query, k, self.ef_search, filter)
+    }
+
+    /// Search for the vectors within `radius` of the query vector, as measured by the distance metric of the index, and
+    /// return at most `max_results` of them sorted by increasing distance. The neighborhood of the query is reached as
+    /// in `search`, then expanded from the closest vectors first through the nodes within the radius. Only the vectors
+    /// visited by this expansion are returned, so as for `search` some vectors within the radius may be missed, and
+    /// when the limit is hit the results are the closest of the visited ones
+    pub fn search_radius(
+        &self,
+        query: &[T; D],
+        radius: f64,
+        max_results: usize,
+    ) -> Result<Vec<SearchResult<'_, T, D>>, HnswError> {
+        let entry_id = self.entry_point.ok_or(HnswError::EmptyIndex)?;
+
+        // locate the neighborhood of the query, deleted nodes included as they still route the expansion
+        let nearest_neighbors = graph::search(
+            self,
+            query,
+            entry_id,
+            self.num_levels() - 1,
+            self.ef_search,
+            |_| true,
+        )?;
+
+        let mut visited = nearest_neighbors
+            .iter()
+            .map(|c| c.id)
+            .collect::<HashSet<_>>();
+        let mut candidates = nearest_neighbors
+            .into_iter()
+            .filter(|c| c.distance <= radius)
+            .map(Reverse)
+            .collect::<BinaryHeap<_>>(); // min heap
+        let mut results = Vec::new();
+
+        while let Some(closest) = candidates.pop().map(|c| c.0) {
+            if results.len() >= max_results {
+                break;
             }
 
-            // perform full search on the lowest level
-            let nearest_neighbors = self
-                .search_level(0, query, &entry_ids, k)
-                .into_iter()
-                .map(|c| SearchResult::new(self.nodes.get(&c.id).unwrap(), c.distance))
-                .collect();
+            if let Some(neighbor_ids) = self.get_neighbors(0, closest.id) {
+                for neighbor_id in neighbor_ids {
+                    if !visited.insert(neighbor_id) {
+                        continue;
+                    }
+
+                    let distance = self.distance(query, self.get_vector(neighbor_id)?)?;
+                    if distance <= radius {
+                        candidates.push(Reverse(Candidate::new(neighbor_id, distance)));
+                    }
+                }
+            }
+
+            if !self.deleted.contains(&closest.id) {
+                results.push(closest);
+            }
+        }
+
+        results.sort_unstable();
+        results
+            .into_iter()
+            .map(|c| Ok(SearchResult::new(c.id, self.get_vector(c.id)?, c.distance)))
+            .collect()
+    }
+
+    /// Search for the k nearest neighbors from the query vector among the live vectors for which `admit` returns true
+    fn search_admitting(
+        &self,
+        query: &[T; D],
+        k: usize,
+        ef: usize,
+        admit: impl Fn(usize) -> bool,
+    ) -> Result<Vec<SearchResult<'_, T, D>>, HnswError> {
+        // the beam below is at least as large as k, so a search without results would not explore anything
+        if k == 0 {
+            return Ok(Vec::new());
+        }
 
-            Ok(nearest_neighbors)
+        if let Some(entry_id) = self.entry_point {
+            // travel the hierarchy from the entry point of the top level, then search the base level with a beam at
+            // least as large as the number of results. Deleted and filtered out nodes are traversed but not returned
+            graph::search(
+                self,
+                query,
+                entry_id,
+                self.num_levels() - 1,
+                ef.max(k),
+                |id| !self.deleted.contains(&id) && admit(id),
+            )?
+            .into_iter()
+            .take(k)
+            .map(|c| Ok(SearchResult::new(c.id, self.get_vector(c.id)?, c.distance)))
+            .collect()
+        } else {
+            Err(HnswError::EmptyIndex)
         }
     }
 
     /// Reset the index by deleting all the vectors and layers
     pub fn clear(&mut self) {
         self.levels = Vec::new();
-        self.nodes = Nodes::new();
+        self.nodes = Vectors::new();
+        self.entry_point = None;
+        self.deleted = HashSet::new();
         self.next_id = 0;
     }
 }
+
+impl<T, const D: usize, F, R> Graph<T> for HNSW<T, D, F, R>
+where
+    T: Sized + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    fn vector(&self, id: usize) -> Result<&[T], HnswError> {
+        Ok(self.get_vector(id)?)
+    }
+
+    fn metric(&self, x: &[T], y: &[T]) -> f64 {
+        (self.distance_metric)(x, y)
+    }
+
+    fn for_each_neighbor(
+        &self,
+        level_index: usize,
+        node_id: usize,
+        visit: impl FnMut(usize) -> Result<(), HnswError>,
+    ) -> Result<(), HnswError> {
+        self.get_neighbors(level_index, node_id)
+            .map_or(Ok(()), |mut neighbor_ids| neighbor_ids.try_for_each(visit))
+    }
+
+    fn parameters(&self) -> Parameters {
+        self.parameters()
+    }
+}
+
+impl<T, const D: usize, F, R> GraphMut<T> for HNSW<T, D, F, R>
+where
+    T: Sized + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    fn levels(&self) -> &[Level] {
+        &self.levels
+    }
+
+    fn levels_mut(&mut self) -> &mut Vec<Level> {
+        &mut self.levels
+    }
+
+    fn entry_point(&self) -> Option<usize> {
+        self.entry_point
+    }
+
+    fn set_entry_point(&mut self, id: usize) {
+        self.entry_point = Some(id);
+    }
+
+    fn sample_max_level_index(&mut self) -> usize {
+        graph::sample_level_index(&mut self.rng, self.level_multiplier)
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<T, const D: usize, F, R> HNSW<T, D, F, R>
+where
+    T: Sized + Copy + Debug + Send + Sync,
+    F: Fn(&[T], &[T]) -> f64 + Sync,
+    R: Rng + Sync,
+{
+    /// Search for the k nearest neighbors of each query vector like `search_batch`, spreading the queries over the
+    /// threads of the rayon pool
+    pub fn par_search_batch(&self, queries: &[[T; D]], k: usize) -> Vec<BatchResult<'_, T, D>> {
+        queries
+            .par_iter()
+            .map(|query| self.search(query, k))
+            .collect()
+    }
+}
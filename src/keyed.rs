@@ -0,0 +1,486 @@
+use crate::{
+    error::HnswError,
+    hnsw::{BatchResult, IndexStats, SearchResult, HNSW},
+};
+use rand::Rng;
+use std::{collections::HashMap, fmt::Debug, hash::Hash, mem};
+
+/// Utility struct to store a nearest neighbor search result of a keyed index
+#[derive(Debug)]
+pub struct KeyedSearchResult<'v, K, T, const D: usize> {
+    pub key: &'v K,
+    pub vector: &'v [T; D],
+    pub distance: f64,
+}
+
+impl<'v, K, T, const D: usize> KeyedSearchResult<'v, K, T, D> {
+    pub fn new(key: &'v K, vector: &'v [T; D], distance: f64) -> Self {
+        Self {
+            key,
+            vector,
+            distance,
+        }
+    }
+}
+
+/// Results of one of the queries of a batch searched in a keyed index
+pub type KeyedBatchResult<'v, K, T, const D: usize> =
+    Result<Vec<KeyedSearchResult<'v, K, T, D>>, HnswError>;
+
+/// Index addressing vectors with keys chosen by the caller, such as document ids, instead of the ids assigned by
+/// `HNSW`. The mapping between keys and internal ids is kept next to the index so that both stay consistent
+pub struct KeyedHNSW<K, T, const D: usize, F, R> {
+    pub(crate) index: HNSW<T, D, F, R>,
+    pub(crate) ids: HashMap<K, usize>,
+    pub(crate) keys: HashMap<usize, K>,
+}
+
+impl<K, T, const D: usize, F, R> KeyedHNSW<K, T, D, F, R>
+where
+    K: Eq + Hash + Clone,
+    T: Sized + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    /// Wrap an empty index, created with `HNSW::new` or `HnswBuilder`. An index storing vectors is rejected, even if
+    /// they are all marked as deleted, as they could be restored without a key
+    pub fn new(index: HNSW<T, D, F, R>) -> Result<Self, HnswError> {
+        if !index.is_empty() || index.stats().num_deleted > 0 {
+            return Err(HnswError::InvalidParameter {
+                name: "index",
+                reason: "must be empty",
+            });
+        }
+
+        Ok(Self {
+            index,
+            ids: HashMap::new(),
+            keys: HashMap::new(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Return the number of vectors stored in the index, excluding the ones marked as deleted
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Return the number of live and deleted vectors as well as the number of levels
+    pub fn stats(&self) -> IndexStats {
+        self.index.stats()
+    }
+
+    /// Return the number of levels in the index
+    pub fn num_levels(&self) -> usize {
+        self.index.num_levels()
+    }
+
+    /// Return the vector stored with the given key, if any
+    pub fn get(&self, key: &K) -> Option<&[T; D]> {
+        self.ids.get(key).and_then(|&id| self.index.get(id))
+    }
+
+    /// Return true if a vector is stored with the given key, even if it is marked as deleted
+    pub fn contains(&self, key: &K) -> bool {
+        self.ids.contains_key(key)
+    }
+
+    /// Return the internal id of a key
+    fn get_id(&self, key: &K) -> Result<usize, HnswError> {
+        self.ids.get(key).copied().ok_or(HnswError::KeyNotFound)
+    }
+
+    /// Mark the vector stored with the given key as deleted, so that searches no longer return it
+    pub fn mark_deleted(&mut self, key: &K) -> Result<(), HnswError> {
+        self.index.mark_deleted(self.get_id(key)?)
+    }
+
+    /// Remove the vector stored with the given key from the index, the key can then be reused
+    pub fn remove(&mut self, key: &K) -> Result<(), HnswError> {
+        let id = self.get_id(key)?;
+        self.index.remove(id)?;
+        self.ids.remove(key);
+        self.keys.remove(&id);
+
+        Ok(())
+    }
+
+    /// Remove every vector marked as deleted from the index, freeing their keys. When `renumber` is true, the internal
+    /// ids of the remaining vectors are made dense, which is transparent for the keys
+    pub fn compact(&mut self, renumber: bool) -> Result<(), HnswError> {
+        let remapping = self.index.compact(renumber)?;
+
+        self.keys = mem::take(&mut self.keys)
+            .into_iter()
+            .filter_map(|(id, key)| Some((*remapping.get(&id)?, key)))
+            .collect();
+        self.ids = self
+            .keys
+            .iter()
+            .map(|(&id, key)| (key.clone(), id))
+            .collect();
+
+        Ok(())
+    }
+
+    /// Replace the vector stored with the given key and rebuild its edges
+    pub fn update(&mut self, key: &K, vector: &[T; D]) -> Result<(), HnswError> {
+        self.index.update(self.get_id(key)?, vector)
+    }
+
+    /// Restore a vector previously marked as deleted so that searches return it again
+    pub fn undelete(&mut self, key: &K) -> Result<(), HnswError> {
+        self.index.undelete(self.get_id(key)?)
+    }
+
+    /// Insert a new vector in the index under the given key, which must not be used already
+    pub fn insert(&mut self, key: K, vector: &[T; D]) -> Result<(), HnswError> {
+        if self.ids.contains_key(&key) {
+            return Err(HnswError::DuplicateKey);
+        }
+
+        let id = self.index.insert(vector)?;
+        self.ids.insert(key.clone(), id);
+        self.keys.insert(id, key);
+
+        Ok(())
+    }
+
+    /// Insert each key and vector of an iterator in the index, stopping at the first one that cannot be inserted
+    pub fn insert_batch<I: Iterator<Item = (K, [T; D])>>(
+        &mut self,
+        batch: I,
+    ) -> Result<(), HnswError> {
+        batch
+            .into_iter()
+            .try_for_each(|(key, ref vector)| self.insert(key, vector))
+    }
+
+    /// Search for the k nearest neighbors from the query vector and return their keys
+    pub fn search(
+        &self,
+        query: &[T; D],
+        k: usize,
+    ) -> Result<Vec<KeyedSearchResult<'_, K, T, D>>, HnswError> {
+        self.with_keys(self.index.search(query, k)?)
+    }
+
+    /// Search for the k nearest neighbors from the query vector, exploring the base level with a dynamic candidate list
+    /// of size `ef`, raised to `k` if smaller
+    pub fn search_with_ef(
+        &self,
+        query: &[T; D],
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<KeyedSearchResult<'_, K, T, D>>, HnswError> {
+        self.with_keys(self.index.search_with_ef(query, k, ef)?)
+    }
+
+    /// Search for the k nearest neighbors from the query vector among the vectors whose key passes the filter, which
+    /// are reached through the other ones
+    pub fn search_filtered(
+        &self,
+        query: &[T; D],
+        k: usize,
+        filter: impl Fn(&K) -> bool,
+    ) -> Result<Vec<KeyedSearchResult<'_, K, T, D>>, HnswError> {
+        let results = self
+            .index
+            .search_filtered(query, k, |id| self.keys.get(&id).is_some_and(&filter))?;
+
+        self.with_keys(results)
+    }
+
+    /// Search for at most `max_results` vectors within `radius` of the query vector and return their keys
+    pub fn search_radius(
+        &self,
+        query: &[T; D],
+        radius: f64,
+        max_results: usize,
+    ) -> Result<Vec<KeyedSearchResult<'_, K, T, D>>, HnswError> {
+        self.with_keys(self.index.search_radius(query, radius, max_results)?)
+    }
+
+    /// Search for the k nearest neighbors of each query vector and return their keys, in the same order as the queries
+    pub fn search_batch(&self, queries: &[[T; D]], k: usize) -> Vec<KeyedBatchResult<'_, K, T, D>> {
+        self.batch_with_keys(self.index.search_batch(queries, k))
+    }
+
+    /// Replace the internal ids of the results of each query of a batch by the keys they are stored with
+    fn batch_with_keys<'v>(
+        &'v self,
+        results: Vec<BatchResult<'v, T, D>>,
+    ) -> Vec<KeyedBatchResult<'v, K, T, D>> {
+        results
+            .into_iter()
+            .map(|results| self.with_keys(results?))
+            .collect()
+    }
+
+    /// Replace the internal ids of search results by the keys they are stored with
+    fn with_keys<'v>(
+        &'v self,
+        results: Vec<SearchResult<'v, T, D>>,
+    ) -> Result<Vec<KeyedSearchResult<'v, K, T, D>>, HnswError> {
+        results
+            .into_iter()
+            .map(|r| {
+                let key = self.keys.get(&r.id).ok_or(HnswError::NodeNotFound(r.id))?;
+                Ok(KeyedSearchResult::new(key, r.vector, r.distance))
+            })
+            .collect()
+    }
+
+    /// Reset the index by deleting all the vectors, layers and keys
+    pub fn clear(&mut self) {
+        self.index.clear();
+        self.ids = HashMap::new();
+        self.keys = HashMap::new();
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<K, T, const D: usize, F, R> KeyedHNSW<K, T, D, F, R>
+where
+    K: Eq + Hash + Clone + Sync,
+    T: Sized + Copy + Debug + Send + Sync,
+    F: Fn(&[T], &[T]) -> f64 + Sync,
+    R: Rng + Sync,
+{
+    /// Search for the k nearest neighbors of each query vector and return their keys like `search_batch`, spreading the
+    /// queries over the threads of the rayon pool
+    pub fn par_search_batch(
+        &self,
+        queries: &[[T; D]],
+        k: usize,
+    ) -> Vec<KeyedBatchResult<'_, K, T, D>> {
+        self.batch_with_keys(self.index.par_search_batch(queries, k))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyedHNSW;
+    use crate::{distances::euclidean, error::HnswError, hnsw::HNSW};
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    const SEED: u64 = 1234;
+
+    #[test]
+    fn test_new_not_empty() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+        index.insert(&[1.0; 2]).unwrap();
+
+        assert!(KeyedHNSW::<u64, _, 2, _, _>::new(index).is_err());
+    }
+
+    #[test]
+    fn test_new_deleted() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+        let id = index.insert(&[1.0; 2]).unwrap();
+        index.mark_deleted(id).unwrap();
+
+        // the deleted vector is still stored, without a key
+        assert!(index.is_empty());
+        assert!(KeyedHNSW::<u64, _, 2, _, _>::new(index).is_err());
+    }
+
+    #[test]
+    fn test_insert_get() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index.insert("a", &[1.0, 2.0]).unwrap();
+        index.insert("b", &[3.0, 4.0]).unwrap();
+
+        assert_eq!(index.len(), 2);
+        assert!(index.contains(&"a"));
+        assert!(!index.contains(&"c"));
+        assert_eq!(index.get(&"b"), Some(&[3.0, 4.0]));
+        assert_eq!(index.get(&"c"), None);
+    }
+
+    #[test]
+    fn test_insert_duplicate_key() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index.insert(42u64, &[1.0, 2.0]).unwrap();
+
+        assert_eq!(index.insert(42, &[3.0, 4.0]), Err(HnswError::DuplicateKey));
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.get(&42), Some(&[1.0, 2.0]));
+    }
+
+    #[test]
+    fn test_search() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index
+            .insert_batch((0..100u64).map(|i| (1000 + i, [i as f64; 2])))
+            .unwrap();
+
+        let result = index.search(&[10.2; 2], 3).unwrap();
+
+        assert_eq!(
+            result.iter().map(|r| *r.key).collect::<Vec<_>>(),
+            &[1010, 1011, 1009]
+        );
+        assert_eq!(result[0].vector, &[10.0; 2]);
+    }
+
+    #[test]
+    fn test_search_filtered() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index
+            .insert_batch((0..100u64).map(|i| (1000 + i, [i as f64; 2])))
+            .unwrap();
+        let results = index
+            .search_filtered(&[10.2; 2], 3, |key| key % 10 == 5)
+            .unwrap();
+
+        let keys = results.iter().map(|r| *r.key).collect::<Vec<_>>();
+        assert_eq!(keys, vec![1015, 1005, 1025]);
+    }
+
+    #[test]
+    fn test_search_radius() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index
+            .insert_batch((0..100u64).map(|i| (1000 + i, [i as f64; 2])))
+            .unwrap();
+        let results = index.search_radius(&[10.2; 2], 2.0, 10).unwrap();
+
+        let keys = results.iter().map(|r| *r.key).collect::<Vec<_>>();
+        assert_eq!(keys, vec![1010, 1011]);
+    }
+
+    #[test]
+    fn test_search_batch() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index
+            .insert_batch((0..100u64).map(|i| (1000 + i, [i as f64; 2])))
+            .unwrap();
+        let results = index.search_batch(&[[10.2; 2], [50.6; 2]], 1);
+
+        let keys = results
+            .into_iter()
+            .map(|results| *results.unwrap()[0].key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![1010, 1051]);
+
+        #[cfg(feature = "parallel")]
+        {
+            let keys = index
+                .par_search_batch(&[[10.2; 2], [50.6; 2]], 1)
+                .into_iter()
+                .map(|results| *results.unwrap()[0].key)
+                .collect::<Vec<_>>();
+            assert_eq!(keys, vec![1010, 1051]);
+        }
+    }
+
+    #[test]
+    fn test_mark_deleted() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index
+            .insert_batch((0..100u64).map(|i| (1000 + i, [i as f64; 2])))
+            .unwrap();
+        index.mark_deleted(&1010).unwrap();
+
+        assert_eq!(index.len(), 99);
+        assert_eq!(index.stats().num_deleted, 1);
+        assert_eq!(*index.search(&[10.2; 2], 1).unwrap()[0].key, 1011);
+        assert_eq!(index.mark_deleted(&0), Err(HnswError::KeyNotFound));
+
+        index.undelete(&1010).unwrap();
+
+        assert_eq!(*index.search(&[10.2; 2], 1).unwrap()[0].key, 1010);
+    }
+
+    #[test]
+    fn test_remove() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index
+            .insert_batch((0..100u64).map(|i| (1000 + i, [i as f64; 2])))
+            .unwrap();
+        index.remove(&1010).unwrap();
+
+        assert_eq!(index.len(), 99);
+        assert!(!index.contains(&1010));
+        assert_eq!(*index.search(&[10.2; 2], 1).unwrap()[0].key, 1011);
+        assert_eq!(index.remove(&1010), Err(HnswError::KeyNotFound));
+
+        // the key is free again
+        index.insert(1010, &[10.0; 2]).unwrap();
+        assert_eq!(*index.search(&[10.2; 2], 1).unwrap()[0].key, 1010);
+    }
+
+    #[test]
+    fn test_compact() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index
+            .insert_batch((0..100u64).map(|i| (1000 + i, [i as f64; 2])))
+            .unwrap();
+        index.mark_deleted(&1000).unwrap();
+        index.mark_deleted(&1010).unwrap();
+        index.compact(true).unwrap();
+
+        assert_eq!(index.len(), 98);
+        assert_eq!(index.stats().num_deleted, 0);
+        assert!(!index.contains(&1010));
+        assert_eq!(index.get(&1011), Some(&[11.0; 2]));
+        assert_eq!(*index.search(&[10.2; 2], 1).unwrap()[0].key, 1011);
+
+        // the key of a compacted vector can be reused
+        index.insert(1010, &[10.0; 2]).unwrap();
+        assert_eq!(*index.search(&[10.2; 2], 1).unwrap()[0].key, 1010);
+    }
+
+    #[test]
+    fn test_update() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index
+            .insert_batch((0..100u64).map(|i| (1000 + i, [i as f64; 2])))
+            .unwrap();
+        index.update(&1010, &[50.5; 2]).unwrap();
+
+        assert_eq!(index.get(&1010), Some(&[50.5; 2]));
+        assert_eq!(*index.search(&[50.6; 2], 1).unwrap()[0].key, 1010);
+        assert_eq!(index.update(&0, &[1.0; 2]), Err(HnswError::KeyNotFound));
+    }
+
+    #[test]
+    fn test_clear() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = KeyedHNSW::new(HNSW::new(8, 8, euclidean, rng).unwrap()).unwrap();
+
+        index
+            .insert_batch((0..10u64).map(|i| (i, [i as f64; 2])))
+            .unwrap();
+        index.clear();
+
+        assert!(index.is_empty());
+        assert!(!index.contains(&0));
+    }
+}
@@ -0,0 +1,465 @@
+use crate::error::HnswError;
+use std::{
+    collections::{HashMap, TryReserveError},
+    fmt,
+    ops::Range,
+    slice,
+};
+
+/// Return the largest id a node of a level can have, the largest value of the width ids are stored on marking vacant
+/// slots
+pub(crate) fn max_id(compact_ids: bool) -> usize {
+    if compact_ids {
+        u32::MAX as usize - 1
+    } else {
+        usize::MAX - 1
+    }
+}
+
+/// Return an error if an id is too large to be stored in the edges of a level
+pub(crate) fn check_id(id: usize, compact_ids: bool) -> Result<(), HnswError> {
+    let max_id = max_id(compact_ids);
+    if id > max_id {
+        return Err(HnswError::IdLimitReached { max_id });
+    }
+
+    Ok(())
+}
+
+/// Ids stored in a level, on 32 bits for an index built with `compact_ids`, which halves the memory taken by the
+/// graph, and as `usize` otherwise. The largest value of the width marks a vacant position
+#[derive(Clone)]
+enum Ids {
+    Compact(Vec<u32>),
+    Wide(Vec<usize>),
+}
+
+impl Ids {
+    fn new(compact_ids: bool) -> Self {
+        if compact_ids {
+            Self::Compact(Vec::new())
+        } else {
+            Self::Wide(Vec::new())
+        }
+    }
+
+    fn is_compact(&self) -> bool {
+        matches!(self, Self::Compact(_))
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Compact(ids) => ids.len(),
+            Self::Wide(ids) => ids.len(),
+        }
+    }
+
+    /// Return the id at a position, or `None` if the position is vacant or out of bounds
+    fn get(&self, position: usize) -> Option<usize> {
+        match self {
+            Self::Compact(ids) => ids
+                .get(position)
+                .filter(|&&id| id != u32::MAX)
+                .map(|&id| id as usize),
+            Self::Wide(ids) => ids.get(position).filter(|&&id| id != usize::MAX).copied(),
+        }
+    }
+
+    fn set(&mut self, position: usize, id: usize) {
+        debug_assert!(
+            id <= max_id(self.is_compact()),
+            "id too large to be stored in a level"
+        );
+
+        match self {
+            Self::Compact(ids) => ids[position] = id as u32,
+            Self::Wide(ids) => ids[position] = id,
+        }
+    }
+
+    fn vacate(&mut self, position: usize) {
+        match self {
+            Self::Compact(ids) => ids[position] = u32::MAX,
+            Self::Wide(ids) => ids[position] = usize::MAX,
+        }
+    }
+
+    fn try_reserve(&mut self, len: usize) -> Result<(), TryReserveError> {
+        match self {
+            Self::Compact(ids) => ids.try_reserve(len.saturating_sub(ids.len())),
+            Self::Wide(ids) => ids.try_reserve(len.saturating_sub(ids.len())),
+        }
+    }
+
+    /// Shorten or extend the ids to a given length, the new positions being vacant
+    fn resize(&mut self, len: usize) {
+        match self {
+            Self::Compact(ids) => ids.resize(len, u32::MAX),
+            Self::Wide(ids) => ids.resize(len, usize::MAX),
+        }
+    }
+
+    fn swap_remove(&mut self, position: usize) {
+        match self {
+            Self::Compact(ids) => _ = ids.swap_remove(position),
+            Self::Wide(ids) => _ = ids.swap_remove(position),
+        }
+    }
+
+    fn copy_within(&mut self, positions: Range<usize>, destination: usize) {
+        match self {
+            Self::Compact(ids) => ids.copy_within(positions, destination),
+            Self::Wide(ids) => ids.copy_within(positions, destination),
+        }
+    }
+
+    fn iter(&self, positions: Range<usize>) -> Neighbors<'_> {
+        match self {
+            Self::Compact(ids) => Neighbors::Compact(ids[positions].iter()),
+            Self::Wide(ids) => Neighbors::Wide(ids[positions].iter()),
+        }
+    }
+}
+
+/// Iterator over the neighbor ids of a node, whatever the width they are stored on
+#[derive(Clone)]
+enum Neighbors<'a> {
+    Compact(slice::Iter<'a, u32>),
+    Wide(slice::Iter<'a, usize>),
+}
+
+impl Iterator for Neighbors<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        match self {
+            Self::Compact(ids) => ids.next().map(|&id| id as usize),
+            Self::Wide(ids) => ids.next().copied(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Compact(ids) => ids.size_hint(),
+            Self::Wide(ids) => ids.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for Neighbors<'_> {}
+
+/// Edges of the nodes of a level, stored in slots of a fixed number of neighbor ids along with the number of them in
+/// use. A slot holds one more edge than the maximum number of connections, as a node gets connected to a new neighbor
+/// before its edges are pruned. In the base level, which holds every node, the slot of a node is its id so reading its
+/// edges is a bounds check. Upper levels hold few nodes, their slots are packed and located by a hash of the id
+#[derive(Clone)]
+pub(crate) struct Level {
+    capacity: usize,
+    slots: Option<HashMap<usize, usize>>,
+    ids: Ids,
+    lengths: Vec<usize>,
+    edges: Ids,
+    len: usize,
+}
+
+impl Level {
+    /// Create an empty level whose nodes have at most `max_connections` edges, with the slots indexed by id when
+    /// `indexed_by_id` is set and the ids stored on 32 bits when `compact_ids` is set
+    pub fn new(max_connections: usize, indexed_by_id: bool, compact_ids: bool) -> Self {
+        Self {
+            capacity: max_connections + 1,
+            slots: (!indexed_by_id).then(HashMap::new),
+            ids: Ids::new(compact_ids),
+            lengths: Vec::new(),
+            edges: Ids::new(compact_ids),
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the maximum number of edges of a node once its connections are pruned
+    pub fn max_connections(&self) -> usize {
+        self.capacity - 1
+    }
+
+    fn slot(&self, id: usize) -> Option<usize> {
+        match &self.slots {
+            Some(slots) => slots.get(&id).copied(),
+            None => (self.ids.get(id) == Some(id)).then_some(id),
+        }
+    }
+
+    fn edges_at(&self, slot: usize) -> Neighbors<'_> {
+        let start = slot * self.capacity;
+        self.edges.iter(start..start + self.lengths[slot])
+    }
+
+    fn push_at(&mut self, slot: usize, neighbor_id: usize) {
+        let length = self.lengths[slot];
+        assert!(length < self.capacity, "more edges than a slot can hold");
+
+        self.edges.set(slot * self.capacity + length, neighbor_id);
+        self.lengths[slot] += 1;
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.slot(id).is_some()
+    }
+
+    /// Return the ids of the neighbors of a node, or `None` if the node is not in the level
+    pub fn get(&self, id: usize) -> Option<impl ExactSizeIterator<Item = usize> + Clone + '_> {
+        self.slot(id).map(|slot| self.edges_at(slot))
+    }
+
+    /// Allocate the slots of `num_slots` nodes, which are the nodes of id below it in a level indexed by id, returning an
+    /// error instead of aborting when the memory cannot be allocated
+    pub fn try_reserve(&mut self, num_slots: usize) -> Result<(), TryReserveError> {
+        self.ids.try_reserve(num_slots)?;
+        self.lengths
+            .try_reserve(num_slots.saturating_sub(self.lengths.len()))?;
+        self.edges
+            .try_reserve(num_slots.saturating_mul(self.capacity))
+    }
+
+    /// Add a node without edges to the level, or remove the edges of a node already in it
+    pub fn insert(&mut self, id: usize) {
+        let slot = match self.slot(id) {
+            Some(slot) => slot,
+            None => {
+                let slot = match &mut self.slots {
+                    Some(slots) => *slots.entry(id).or_insert(self.ids.len()),
+                    None => id,
+                };
+                if slot >= self.ids.len() {
+                    self.ids.resize(slot + 1);
+                    self.lengths.resize(slot + 1, 0);
+                    self.edges.resize((slot + 1) * self.capacity);
+                }
+                self.ids.set(slot, id);
+                self.len += 1;
+
+                slot
+            }
+        };
+
+        self.lengths[slot] = 0;
+    }
+
+    /// Add an edge from a node towards a neighbor, return `None` if the node is not in the level
+    pub fn push(&mut self, id: usize, neighbor_id: usize) -> Option<()> {
+        let slot = self.slot(id)?;
+        self.push_at(slot, neighbor_id);
+
+        Some(())
+    }
+
+    /// Replace the edges of a node, return `None` if the node is not in the level
+    pub fn set(&mut self, id: usize, edges: impl IntoIterator<Item = usize>) -> Option<()> {
+        let slot = self.slot(id)?;
+        self.lengths[slot] = 0;
+        for neighbor_id in edges {
+            self.push_at(slot, neighbor_id);
+        }
+
+        Some(())
+    }
+
+    /// Remove the edges of a node and return them, leaving the node in the level
+    pub fn take(&mut self, id: usize) -> Option<Vec<usize>> {
+        let edges = self.get(id)?.collect();
+        self.set(id, [])?;
+
+        Some(edges)
+    }
+
+    /// Remove a node from the level and return its edges. Vacant slots at the end of a level indexed by id are
+    /// released, and the last slot of a hashed level is moved to the one freed to keep them packed
+    pub fn remove(&mut self, id: usize) -> Option<Vec<usize>> {
+        let slot = self.slot(id)?;
+        let edges = self.get(id)?.collect();
+        self.len -= 1;
+
+        match &mut self.slots {
+            Some(slots) => {
+                let last = self.ids.len() - 1;
+                slots.remove(&id);
+                if slot != last {
+                    let last_id = self
+                        .ids
+                        .get(last)
+                        .expect("slots of a hashed level are packed");
+                    slots.insert(last_id, slot);
+                    self.edges.copy_within(
+                        last * self.capacity..(last + 1) * self.capacity,
+                        slot * self.capacity,
+                    );
+                }
+                self.ids.swap_remove(slot);
+                self.lengths.swap_remove(slot);
+            }
+            None => {
+                self.ids.vacate(slot);
+                while let Some(last) = self.ids.len().checked_sub(1) {
+                    if self.ids.get(last).is_some() {
+                        break;
+                    }
+                    self.ids.resize(last);
+                    self.lengths.truncate(last);
+                }
+            }
+        }
+        self.edges.resize(self.ids.len() * self.capacity);
+
+        Some(edges)
+    }
+
+    /// Return the ids of the nodes in the level, in the order of their slots
+    pub fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.ids.len()).filter_map(|slot| self.ids.get(slot))
+    }
+
+    /// Return the nodes of the level along with the ids of their neighbors, in the order of their slots
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (usize, impl ExactSizeIterator<Item = usize> + Clone + '_)> + '_ {
+        (0..self.ids.len()).filter_map(|slot| Some((self.ids.get(slot)?, self.edges_at(slot))))
+    }
+
+    /// Replace the id of every node and neighbor by the one it is mapped to, which must be defined for all of them
+    pub fn renumber(&mut self, remapping: &HashMap<usize, usize>) {
+        let mut level = Self::new(
+            self.max_connections(),
+            self.slots.is_none(),
+            self.ids.is_compact(),
+        );
+        for (id, edges) in self.iter() {
+            level.insert(remapping[&id]);
+            level.set(remapping[&id], edges.map(|id| remapping[&id]));
+        }
+
+        *self = level;
+    }
+}
+
+/// Two levels are equal if they hold the same nodes with the same edges, whatever their slots
+impl PartialEq for Level {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len
+            && self
+                .iter()
+                .all(|(id, edges)| other.get(id).is_some_and(|other| other.eq(edges)))
+    }
+}
+
+impl fmt::Debug for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.iter()
+                    .map(|(id, edges)| (id, edges.collect::<Vec<_>>())),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_id, max_id, Ids, Level};
+    use crate::error::HnswError;
+    use std::{collections::HashMap, mem};
+
+    fn edges(level: &Level, id: usize) -> Vec<usize> {
+        level.get(id).unwrap().collect()
+    }
+
+    #[test]
+    fn test_level_indexed_by_id() {
+        for compact_ids in [false, true] {
+            check_level_indexed_by_id(Level::new(2, true, compact_ids));
+        }
+    }
+
+    fn check_level_indexed_by_id(mut level: Level) {
+        level.insert(0);
+        level.insert(3);
+        assert_eq!(level.ids().count(), 2);
+        assert!(!level.contains(1));
+        assert_eq!(level.ids().collect::<Vec<_>>(), [0, 3]);
+
+        // one more edge than the maximum fits until the node is pruned
+        level.set(0, [3, 1, 2]).unwrap();
+        assert_eq!(edges(&level, 0), [3, 1, 2]);
+        assert_eq!(level.push(2, 0), None);
+        level.push(3, 0).unwrap();
+
+        assert_eq!(level.take(0), Some(Vec::from([3, 1, 2])));
+        assert!(edges(&level, 0).is_empty());
+
+        assert_eq!(level.remove(3), Some(Vec::from([0])));
+        assert_eq!(level.remove(3), None);
+        assert_eq!(level.ids().count(), 1);
+        assert_eq!(level.edges.len(), 3);
+    }
+
+    #[test]
+    fn test_level_hashed() {
+        for compact_ids in [false, true] {
+            check_level_hashed(compact_ids);
+        }
+    }
+
+    fn check_level_hashed(compact_ids: bool) {
+        let mut level = Level::new(2, false, compact_ids);
+        for id in [7, 2, 5] {
+            level.insert(id);
+            level.set(id, [id, id]).unwrap();
+        }
+
+        // the last slot moves to the one freed, keeping the edges of its node
+        assert_eq!(level.remove(7), Some(Vec::from([7, 7])));
+        assert_eq!(level.ids().collect::<Vec<_>>(), [5, 2]);
+        assert_eq!(edges(&level, 5), [5, 5]);
+        assert_eq!(level.edges.len(), 2 * 3);
+
+        let mut other = Level::new(2, false, compact_ids);
+        for id in [2, 5] {
+            other.insert(id);
+            other.set(id, [id, id]).unwrap();
+        }
+        assert_eq!(level, other);
+
+        level.renumber(&HashMap::from([(2, 0), (5, 1)]));
+        assert_eq!(edges(&level, 0), [0, 0]);
+        assert_eq!(edges(&level, 1), [1, 1]);
+    }
+
+    #[test]
+    fn test_level_compact_ids() {
+        let bytes = |ids: &Ids| match ids {
+            Ids::Compact(ids) => mem::size_of_val(ids.as_slice()),
+            Ids::Wide(ids) => mem::size_of_val(ids.as_slice()),
+        };
+
+        // a slot of 16 edges, once connected to 17 neighbors before pruning, takes half the memory with 32-bit ids
+        for (compact_ids, slot_bytes) in [(false, 17 * 8), (true, 17 * 4)] {
+            let mut level = Level::new(16, true, compact_ids);
+            level.insert(0);
+            level.set(0, 1..=17).unwrap();
+
+            assert_eq!(bytes(&level.edges), slot_bytes);
+            assert!(level.get(0).unwrap().eq(1..=17));
+        }
+
+        let limit = u32::MAX as usize - 1;
+        assert_eq!(max_id(true), limit);
+        assert_eq!(check_id(limit, true), Ok(()));
+        assert_eq!(
+            check_id(limit + 1, true),
+            Err(HnswError::IdLimitReached { max_id: limit })
+        );
+        assert_eq!(check_id(limit + 1, false), Ok(()));
+    }
+}
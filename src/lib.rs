@@ -1,66 +1,174 @@
+mod arena;
+pub mod builder;
+pub mod concurrent;
 pub mod distances;
+pub mod dynamic;
+pub mod error;
+pub mod format;
+mod graph;
 pub mod hnsw;
+pub mod keyed;
+mod level;
+#[cfg(target_endian = "little")]
+pub mod mmap;
+#[cfg(feature = "serde")]
+pub mod serialization;
 
 #[cfg(test)]
 mod tests {
-    use super::{distances::euclidean, hnsw::HNSW};
-    use rand::{rngs::SmallRng, SeedableRng};
+    use super::{
+        builder::HnswBuilder,
+        concurrent::ConcurrentHNSW,
+        distances::{cosine, euclidean},
+        error::HnswError,
+        hnsw::{IndexStats, NeighborSelection, HNSW},
+    };
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+    use std::{cell::Cell, collections::HashSet, sync::Arc, thread};
 
     const SEED: u64 = 1234;
 
+    /// Sample vectors around `num_clusters` random centers, far apart from each others
+    fn sample_clusters<const D: usize>(size: usize, num_clusters: usize) -> Vec<[f64; D]> {
+        let mut rng = SmallRng::seed_from_u64(SEED);
+        let centers = (0..num_clusters)
+            .map(|_| [(); D].map(|_| rng.gen_range(-100.0..100.0)))
+            .collect::<Vec<_>>();
+
+        (0..size)
+            .map(|i| centers[i % num_clusters].map(|c| c + rng.gen_range(-1.0..1.0)))
+            .collect()
+    }
+
+    /// Fraction of the true k nearest neighbors of each query found by the index
+    fn recall<const D: usize, F: Fn(&[f64], &[f64]) -> f64>(
+        index: &HNSW<f64, D, F, SmallRng>,
+        vectors: &[[f64; D]],
+        queries: &[[f64; D]],
+        k: usize,
+    ) -> f64 {
+        let mut found = 0;
+
+        for query in queries {
+            let mut distances = vectors
+                .iter()
+                .map(|v| euclidean(query, v))
+                .collect::<Vec<_>>();
+            distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let threshold = distances[k - 1];
+
+            found += index
+                .search(query, k)
+                .unwrap()
+                .iter()
+                .filter(|r| r.distance <= threshold)
+                .count();
+        }
+
+        found as f64 / (k * queries.len()) as f64
+    }
+
+    /// Number of nodes reachable from `start` by following the edges of the base level
+    fn count_reachable<const D: usize, F, R>(index: &HNSW<f64, D, F, R>, start: usize) -> usize {
+        let mut visited = HashSet::from([start]);
+        let mut stack = vec![start];
+
+        while let Some(id) = stack.pop() {
+            for neighbor_id in index.levels[0].get(id).unwrap() {
+                if visited.insert(neighbor_id) {
+                    stack.push(neighbor_id);
+                }
+            }
+        }
+
+        visited.len()
+    }
+
     #[test]
     fn test_new() {
         let rng = SmallRng::seed_from_u64(SEED);
-        let index: HNSW<f64, 3, _, _> = HNSW::new(1, 1, euclidean, rng);
+        let index: HNSW<f64, 3, _, _> = HNSW::new(2, 2, euclidean, rng).unwrap();
 
         assert!(index.is_empty());
         assert_eq!(index.len(), 0);
         assert_eq!(index.num_levels(), 0);
     }
 
+    #[test]
+    fn test_new_invalid() {
+        // with M = 1 the level multiplier 1 / ln(M) is infinite
+        let rng = SmallRng::seed_from_u64(SEED);
+        let result: Result<HNSW<f64, 3, _, _>, _> = HNSW::new(1, 1, euclidean, rng);
+
+        assert!(matches!(
+            result,
+            Err(HnswError::InvalidParameter {
+                name: "level_multiplier",
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn test_insert() {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(8, 8, euclidean, rng);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
 
         let vector1 = [1., 2., 3.];
         let vector2 = [4., 5., 6.];
         let vector3 = [7., 8., 9.];
 
-        index.insert(&vector1);
-        index.insert(&vector2);
-        index.insert(&vector3);
+        let id1 = index.insert(&vector1).unwrap();
+        let id2 = index.insert(&vector2).unwrap();
+        let id3 = index.insert(&vector3).unwrap();
 
         assert!(!index.is_empty());
         assert_eq!(index.len(), 3);
-        assert!(index.nodes.values().any(|v| v == &vector1));
-        assert!(index.nodes.values().any(|v| v == &vector2));
-        assert!(index.nodes.values().any(|v| v == &vector3));
+        assert_eq!(index.nodes[id1], vector1);
+        assert_eq!(index.nodes[id2], vector2);
+        assert_eq!(index.nodes[id3], vector3);
     }
 
     #[test]
     fn test_insert_iterator() {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(8, 8, euclidean, rng);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
         let iterator = (0..3).map(|i| [i as f64; 2]);
 
-        index.insert_batch(iterator);
+        let ids = index.insert_batch(iterator).unwrap();
 
         assert!(!index.is_empty());
         assert_eq!(index.len(), 3);
+        assert!(ids
+            .iter()
+            .enumerate()
+            .all(|(i, id)| index.nodes[*id] == [i as f64; 2]));
+    }
+
+    #[test]
+    fn test_get() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let id = index.insert(&[1., 2., 3.]).unwrap();
+
+        assert!(index.contains(id));
+        assert!(!index.contains(id + 1));
+        assert_eq!(index.get(id), Some(&[1., 2., 3.]));
+        assert_eq!(index.get(id + 1), None);
     }
 
     #[test]
     fn test_level_density_decay() {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(8, 8, euclidean, rng);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
 
-        index.insert_batch((0..10).map(|i| [i as f64; 2]));
+        index.insert_batch((0..10).map(|i| [i as f64; 2])).unwrap();
 
         // check that the number of nodes in levels is smaller the higher the level
         let structure_ok = index.levels.windows(2).all(|w| {
             let (layer_0, layer_1) = (&w[0], &w[1]);
-            layer_0.len() >= layer_1.len()
+            layer_0.ids().count() >= layer_1.ids().count()
         });
 
         assert!(structure_ok);
@@ -69,12 +177,12 @@ mod tests {
     #[test]
     fn test_max_connections() {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(8, 8, euclidean, rng);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
 
-        index.insert_batch((0..10).map(|i| [i as f64; 2]));
+        index.insert_batch((0..10).map(|i| [i as f64; 2])).unwrap();
 
         let structure_ok = index.levels.iter().enumerate().all(|(level_index, level)| {
-            level.values().all(move |edges| {
+            level.iter().all(move |(_, edges)| {
                 let max_connections = if level_index > 0 {
                     index.max_connections
                 } else {
@@ -87,22 +195,177 @@ mod tests {
         assert!(structure_ok);
     }
 
+    #[test]
+    fn test_max_connections_heuristic() {
+        for (extend_candidates, keep_pruned_connections) in
+            [(false, false), (true, false), (false, true), (true, true)]
+        {
+            let mut index = HnswBuilder::new(4)
+                .ef_construction(16)
+                .neighbor_selection(NeighborSelection::Heuristic {
+                    extend_candidates,
+                    keep_pruned_connections,
+                })
+                .seed(SEED)
+                .build(euclidean)
+                .unwrap();
+
+            index
+                .insert_batch(sample_clusters::<4>(200, 4).into_iter())
+                .unwrap();
+
+            let structure_ok = index.levels.iter().enumerate().all(|(level_index, level)| {
+                let max_connections = if level_index > 0 {
+                    index.max_connections
+                } else {
+                    index.max_connections_0
+                };
+                level
+                    .iter()
+                    .all(|(_, edges)| edges.len() <= max_connections)
+            });
+
+            assert!(structure_ok);
+        }
+    }
+
+    #[test]
+    fn test_pruning_keeps_base_level_connected() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(4, 32, euclidean, rng).unwrap();
+
+        index
+            .insert_batch(sample_clusters::<2>(1000, 5).into_iter())
+            .unwrap();
+
+        // every node of the base level must be reachable from the top of the hierarchy
+        assert_eq!(
+            count_reachable(&index, index.entry_point.unwrap()),
+            index.len()
+        );
+    }
+
+    #[test]
+    fn test_recall_clustered() {
+        let vectors = sample_clusters::<8>(1000, 10);
+        let queries = sample_clusters::<8>(50, 7);
+
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 64, euclidean, rng).unwrap();
+        index.insert_batch(vectors.iter().copied()).unwrap();
+
+        assert!(recall(&index, &vectors, &queries, 10) > 0.9);
+    }
+
+    #[test]
+    fn test_concurrent_build_recall() {
+        // vectors spread uniformly, on which recall is lower than on clusters
+        let vectors = sample_clusters::<16>(2000, 2000);
+        let queries = sample_clusters::<16>(50, 50);
+        let builder = HnswBuilder::new(6).ef_construction(32).seed(SEED);
+
+        let mut sequential = builder.build(euclidean).unwrap();
+        sequential.insert_batch(vectors.iter().copied()).unwrap();
+
+        let concurrent: ConcurrentHNSW<f64, 16, _, _> =
+            builder.build_concurrent(euclidean).unwrap();
+        thread::scope(|scope| {
+            for chunk in vectors.chunks(250) {
+                let concurrent = &concurrent;
+                scope.spawn(move || {
+                    for vector in chunk {
+                        concurrent.insert(vector).unwrap();
+                    }
+                });
+            }
+        });
+        let concurrent = concurrent.into_index();
+
+        // the graph built concurrently is as good as the one built sequentially, up to the variations caused by the
+        // order of insertion
+        assert_eq!(concurrent.len(), vectors.len());
+        let expected = recall(&sequential, &vectors, &queries, 10);
+        assert!(recall(&concurrent, &vectors, &queries, 10) >= expected - 0.05);
+    }
+
     #[test]
     fn test_search_empty() {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(8, 8, euclidean, rng);
+        let index = HNSW::new(8, 8, euclidean, rng).unwrap();
         let vector = [1., 2., 3.];
 
-        assert!(index.search(&vector, 1).is_err());
+        assert!(matches!(
+            index.search(&vector, 1),
+            Err(HnswError::EmptyIndex)
+        ));
+        assert!(matches!(
+            index.search_radius(&vector, 1.0, 1),
+            Err(HnswError::EmptyIndex)
+        ));
+    }
+
+    #[test]
+    fn test_insert_invalid_distance() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, cosine, rng).unwrap();
+
+        index.insert(&[1., 2., 3.]).unwrap();
+
+        // the cosine distance is undefined for a zero vector, and any distance involving NaN is NaN
+        assert_eq!(index.insert(&[0.; 3]), Err(HnswError::InvalidDistance));
+        assert_eq!(
+            index.insert(&[f64::NAN, 1., 1.]),
+            Err(HnswError::InvalidDistance)
+        );
+        assert_eq!(index.len(), 1);
+
+        assert_eq!(
+            index.search(&[0.; 3], 1).unwrap_err(),
+            HnswError::InvalidDistance
+        );
+    }
+
+    #[test]
+    fn test_insert_id_limit() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        index.insert(&[0.0; 2]).unwrap();
+
+        // every id that fits in the edges of the levels has been assigned
+        index.next_id = usize::MAX;
+        assert_eq!(
+            index.insert(&[1.0; 2]),
+            Err(HnswError::IdLimitReached {
+                max_id: usize::MAX - 1
+            })
+        );
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.search(&[1.0; 2], 2).unwrap().len(), 1);
+
+        // ids stored on 32 bits run out sooner
+        let mut index = HnswBuilder::new(8)
+            .compact_ids(true)
+            .seed(SEED)
+            .build(euclidean)
+            .unwrap();
+        index.insert(&[0.0; 2]).unwrap();
+        index.next_id = u32::MAX as usize;
+        assert_eq!(
+            index.insert(&[1.0; 2]),
+            Err(HnswError::IdLimitReached {
+                max_id: u32::MAX as usize - 1
+            })
+        );
     }
 
     #[test]
     fn test_search_exact() {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(8, 8, euclidean, rng);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
         let vector = [1., 2., 3.];
 
-        index.insert(&vector);
+        index.insert(&vector).unwrap();
         let result = index.search(&vector, 1).unwrap();
 
         assert_eq!(result.len(), 1);
@@ -113,15 +376,15 @@ mod tests {
     #[test]
     fn test_search() {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(8, 8, euclidean, rng);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
 
         let vector1 = [1., 2., 3.];
         let vector2 = [0., 0., 0.];
         let vector3 = [10., 20., 30.];
 
-        index.insert(&vector1);
-        index.insert(&vector2);
-        index.insert(&vector3);
+        let id1 = index.insert(&vector1).unwrap();
+        let id2 = index.insert(&vector2).unwrap();
+        let id3 = index.insert(&vector3).unwrap();
 
         let query = [1.1, 2.1, 3.1];
         let result = index.search(&query, 3).unwrap();
@@ -131,14 +394,662 @@ mod tests {
             result.iter().map(|r| r.vector).collect::<Vec<_>>(),
             &[&vector1, &vector2, &vector3]
         );
+        assert_eq!(
+            result.iter().map(|r| r.id).collect::<Vec<_>>(),
+            &[id1, id2, id3]
+        );
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        assert!(index.entry_point.is_none());
+
+        index
+            .insert_batch(sample_clusters::<2>(100, 1).into_iter())
+            .unwrap();
+
+        // the entry point belongs to the top level, and so to every level below
+        let entry_id = index.entry_point.unwrap();
+        assert!(index.levels.iter().all(|level| level.contains(entry_id)));
+    }
+
+    #[test]
+    fn test_search_deterministic() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        index
+            .insert_batch(sample_clusters::<2>(100, 4).into_iter())
+            .unwrap();
+
+        let query = [0.5, -0.5];
+        let first = index.search(&query, 5).unwrap();
+        let second = index.search(&query, 5).unwrap();
+
+        assert_eq!(
+            first.iter().map(|r| r.vector).collect::<Vec<_>>(),
+            second.iter().map(|r| r.vector).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_search_with_ef() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+
+        // the beam is never smaller than k, and only the k closest vectors of the beam are returned
+        for ef in [0, 1, 3, 50] {
+            let result = index.search_with_ef(&[10.2; 2], 3, ef).unwrap();
+
+            assert_eq!(
+                result.iter().map(|r| r.vector).collect::<Vec<_>>(),
+                &[&[10.0; 2], &[11.0; 2], &[9.0; 2]]
+            );
+        }
+
+        assert!(index.search_with_ef(&[10.2; 2], 0, 0).unwrap().is_empty());
+        assert!(matches!(
+            index.with_ef_search(0),
+            Err(HnswError::InvalidParameter {
+                name: "ef_search",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_ef_search_recall() {
+        let vectors = sample_clusters::<8>(1000, 10);
+        let queries = sample_clusters::<8>(50, 7);
+
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 64, euclidean, rng)
+            .unwrap()
+            .with_ef_search(1)
+            .unwrap();
+        index.insert_batch(vectors.iter().copied()).unwrap();
+        let recall_small_beam = recall(&index, &vectors, &queries, 1);
+
+        let index = index.with_ef_search(64).unwrap();
+        let recall_large_beam = recall(&index, &vectors, &queries, 1);
+
+        assert!(recall_large_beam >= recall_small_beam);
+        assert!(recall_large_beam > 0.95);
+    }
+
+    #[test]
+    fn test_search_filtered() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let ids = index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+        index.mark_deleted(ids[31]).unwrap();
+
+        let results = index
+            .search_filtered(&[30.2; 2], 5, |id| id % 10 == 1)
+            .unwrap();
+        let found = results.iter().map(|r| r.id).collect::<Vec<_>>();
+
+        // deleted vectors are still excluded
+        assert_eq!(found, vec![ids[21], ids[41], ids[11], ids[51], ids[1]]);
+        assert!(index
+            .search_filtered(&[30.2; 2], 5, |_| false)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_search_filtered_recall() {
+        let vectors = sample_clusters::<8>(1000, 10);
+        let queries = sample_clusters::<8>(50, 7);
+
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 64, euclidean, rng).unwrap();
+        let ids = index.insert_batch(vectors.iter().copied()).unwrap();
+
+        // only a tenth of the vectors pass the filter, spread over all clusters
+        let allowed = ids.iter().copied().step_by(7).collect::<HashSet<_>>();
+        let allowed_vectors = vectors.iter().step_by(7).copied().collect::<Vec<_>>();
+
+        let k = 10;
+        let mut found = 0;
+        for query in &queries {
+            let mut distances = allowed_vectors
+                .iter()
+                .map(|v| euclidean(query, v))
+                .collect::<Vec<_>>();
+            distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let results = index
+                .search_filtered(query, k, |id| allowed.contains(&id))
+                .unwrap();
+            assert!(results.iter().all(|r| allowed.contains(&r.id)));
+
+            found += results
+                .iter()
+                .filter(|r| r.distance <= distances[k - 1])
+                .count();
+        }
+
+        assert!(found as f64 / (k * queries.len()) as f64 > 0.9);
+    }
+
+    #[test]
+    fn test_search_radius() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let ids = index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+        index.mark_deleted(ids[12]).unwrap();
+
+        // the squared euclidean distance to [i; 2] is 2 * (i - 10.2)^2, deleted vectors are excluded
+        let results = index.search_radius(&[10.2; 2], 8.0, 10).unwrap();
+        let found = results.iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(found, vec![ids[10], ids[11], ids[9]]);
+
+        // only the closest vectors are returned when there are more than `max_results`
+        let results = index.search_radius(&[10.2; 2], 200.0, 4).unwrap();
+        let found = results.iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(found, vec![ids[10], ids[11], ids[9], ids[8]]);
+
+        assert!(index
+            .search_radius(&[-10.0; 2], 8.0, 10)
+            .unwrap()
+            .is_empty());
+        assert_eq!(index.search_radius(&[10.2; 2], 8.0, 0).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_search_radius_clustered() {
+        let vectors = sample_clusters::<8>(1000, 10);
+        let queries = sample_clusters::<8>(50, 7);
+
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 64, euclidean, rng).unwrap();
+        index.insert_batch(vectors.iter().copied()).unwrap();
+
+        // a radius covering part of the cluster of each query
+        let radius = 4.0;
+        let mut expected = 0;
+        let mut found = 0;
+
+        for query in &queries {
+            expected += vectors
+                .iter()
+                .filter(|v| euclidean(query, *v) <= radius)
+                .count();
+
+            let results = index.search_radius(query, radius, usize::MAX).unwrap();
+            assert!(results.iter().all(|r| r.distance <= radius));
+            assert!(results.windows(2).all(|w| w[0].distance <= w[1].distance));
+            found += results.len();
+        }
+
+        assert!(expected > 0);
+        assert!(found as f64 / expected as f64 > 0.95);
+    }
+
+    #[test]
+    fn test_search_overlapping_results() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        index.insert_batch((0..10).map(|i| [i as f64; 2])).unwrap();
+
+        // results borrow the index immutably, so several of them can be alive at the same time
+        let first = index.search(&[0.0; 2], 1).unwrap();
+        let second = index.search(&[9.0; 2], 1).unwrap();
+
+        assert_eq!(first[0].vector, &[0.0; 2]);
+        assert_eq!(second[0].vector, &[9.0; 2]);
+    }
+
+    #[test]
+    fn test_search_shared() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+
+        let index = Arc::new(index);
+        let handles = (0..4)
+            .map(|i| {
+                let index = Arc::clone(&index);
+                thread::spawn(move || *index.search(&[i as f64; 2], 1).unwrap()[0].vector)
+            })
+            .collect::<Vec<_>>();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), [i as f64; 2]);
+        }
+    }
+
+    #[test]
+    fn test_search_batch() {
+        let vectors = sample_clusters::<8>(1000, 10);
+        let queries = sample_clusters::<8>(50, 7);
+
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 64, euclidean, rng).unwrap();
+        index.insert_batch(vectors.into_iter()).unwrap();
+
+        let results = index.search_batch(&queries, 10);
+
+        // results are returned in the order of the queries, and match the ones of single searches
+        assert_eq!(results.len(), queries.len());
+        for (query, results) in queries.iter().zip(results) {
+            let found = results.unwrap().iter().map(|r| r.id).collect::<Vec<_>>();
+            let expected = index.search(query, 10).unwrap();
+            assert_eq!(found, expected.iter().map(|r| r.id).collect::<Vec<_>>());
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_par_search_batch() {
+        let vectors = sample_clusters::<8>(1000, 10);
+        let queries = sample_clusters::<8>(50, 7);
+
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 64, euclidean, rng).unwrap();
+        index.insert_batch(vectors.into_iter()).unwrap();
+
+        // the queries spread over threads find the same neighbors as when answered in order
+        let results = index.par_search_batch(&queries, 10);
+        assert_eq!(results.len(), queries.len());
+        for (results, expected) in results.into_iter().zip(index.search_batch(&queries, 10)) {
+            let found = results.unwrap().iter().map(|r| r.id).collect::<Vec<_>>();
+            let expected = expected.unwrap().iter().map(|r| r.id).collect::<Vec<_>>();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_search_batch_errors() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, cosine, rng).unwrap();
+
+        assert!(index
+            .search_batch(&[[1., 2., 3.], [3., 2., 1.]], 1)
+            .into_iter()
+            .all(|results| results.unwrap_err() == HnswError::EmptyIndex));
+
+        index.insert(&[1., 2., 3.]).unwrap();
+
+        // a failing query doesn't prevent the others from being answered
+        let results = index.search_batch(&[[1., 2., 3.], [0.; 3], [3., 2., 1.]], 1);
+        assert_eq!(results[0].as_ref().unwrap()[0].vector, &[1., 2., 3.]);
+        assert_eq!(
+            results[1].as_ref().unwrap_err(),
+            &HnswError::InvalidDistance
+        );
+        assert_eq!(results[2].as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_mark_deleted() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let ids = index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+        index.mark_deleted(ids[10]).unwrap();
+
+        assert_eq!(index.len(), 99);
+        assert!(index.is_deleted(ids[10]));
+        assert_eq!(
+            index.stats(),
+            IndexStats {
+                num_live: 99,
+                num_deleted: 1,
+                num_levels: index.num_levels()
+            }
+        );
+
+        let result = index.search(&[10.2; 2], 3).unwrap();
+        assert_eq!(
+            result.iter().map(|r| r.id).collect::<Vec<_>>(),
+            &[ids[11], ids[9], ids[12]]
+        );
+
+        index.undelete(ids[10]).unwrap();
+
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.search(&[10.2; 2], 1).unwrap()[0].id, ids[10]);
+    }
+
+    #[test]
+    fn test_mark_deleted_unknown() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index: HNSW<f64, 2, _, _> = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        assert_eq!(index.mark_deleted(0), Err(HnswError::NodeNotFound(0)));
+        assert_eq!(index.undelete(0), Err(HnswError::NodeNotFound(0)));
+    }
+
+    #[test]
+    fn test_mark_deleted_routing() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        index
+            .insert_batch(sample_clusters::<2>(200, 4).into_iter())
+            .unwrap();
+
+        // a deleted entry point keeps routing searches
+        let entry_id = index.entry_point.unwrap();
+        index.mark_deleted(entry_id).unwrap();
+
+        let result = index.search(index.get(entry_id).unwrap(), 10).unwrap();
+        assert_eq!(result.len(), 10);
+        assert!(result.iter().all(|r| r.id != entry_id));
+
+        // once every node is deleted, searches return nothing
+        for id in 0..200 {
+            index.mark_deleted(id).unwrap();
+        }
+
+        assert!(index.is_empty());
+        assert!(index.search(&[0.0; 2], 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let ids = index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+        index.mark_deleted(ids[20]).unwrap();
+        index.remove(ids[10]).unwrap();
+        index.remove(ids[20]).unwrap();
+
+        assert_eq!(index.len(), 98);
+        assert_eq!(index.stats().num_deleted, 0);
+        assert!(!index.contains(ids[10]));
+        assert_eq!(index.remove(ids[10]), Err(HnswError::NodeNotFound(ids[10])));
+
+        // no level references the removed nodes anymore
+        assert!(index.levels.iter().all(|level| {
+            !level.contains(ids[10])
+                && level
+                    .iter()
+                    .flat_map(|(_, edges)| edges)
+                    .all(|id| id != ids[10] && id != ids[20])
+        }));
+
+        assert_eq!(index.search(&[10.2; 2], 1).unwrap()[0].id, ids[11]);
+    }
+
+    #[test]
+    fn test_remove_entry_point() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(4, 16, euclidean, rng).unwrap();
+
+        index
+            .insert_batch(sample_clusters::<2>(200, 4).into_iter())
+            .unwrap();
+
+        while let Some(entry_id) = index.entry_point {
+            index.remove(entry_id).unwrap();
+
+            // the top level is never empty, and the new entry point belongs to it
+            if let Some(entry_id) = index.entry_point {
+                let top_level = index.levels.last().unwrap();
+                assert!(top_level.contains(entry_id));
+                assert!(index.levels.iter().all(|level| !level.is_empty()));
+            }
+        }
+
+        assert!(index.is_empty());
+        assert_eq!(index.num_levels(), 0);
+        assert_eq!(
+            index.search(&[0.0; 2], 1).unwrap_err(),
+            HnswError::EmptyIndex
+        );
+    }
+
+    #[test]
+    fn test_remove_recall() {
+        let vectors = sample_clusters::<8>(1000, 10);
+        let queries = sample_clusters::<8>(50, 7);
+
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 64, euclidean, rng).unwrap();
+        let ids = index.insert_batch(vectors.iter().copied()).unwrap();
+
+        // remove every other vector
+        for &id in ids.iter().step_by(2) {
+            index.remove(id).unwrap();
+        }
+        let remaining = vectors
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .copied()
+            .collect::<Vec<_>>();
+
+        assert_eq!(index.len(), 500);
+        assert_eq!(
+            count_reachable(&index, index.entry_point.unwrap()),
+            index.len()
+        );
+        assert!(recall(&index, &remaining, &queries, 10) > 0.9);
+    }
+
+    #[test]
+    fn test_compact() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let ids = index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+        for &id in ids.iter().step_by(2) {
+            index.mark_deleted(id).unwrap();
+        }
+        let remapping = index.compact(false).unwrap();
+
+        assert_eq!(index.len(), 50);
+        assert_eq!(index.stats().num_deleted, 0);
+        assert_eq!(remapping.len(), 50);
+        assert!(remapping
+            .iter()
+            .all(|(id, new_id)| id == new_id && id % 2 == 1));
+
+        // no level references the removed nodes anymore, and the entry point is in the top level
+        assert!(index.levels.iter().all(|level| level
+            .ids()
+            .chain(level.iter().flat_map(|(_, edges)| edges))
+            .all(|id| id % 2 == 1)));
+        assert!(index
+            .levels
+            .last()
+            .unwrap()
+            .contains(index.entry_point.unwrap()));
+
+        assert_eq!(index.search(&[10.2; 2], 1).unwrap()[0].id, ids[11]);
+        assert_eq!(index.insert(&[0.0; 2]).unwrap(), 100);
+    }
+
+    #[test]
+    fn test_compact_error() {
+        // the distance fails once it has been called a given number of times, at various steps of the compaction
+        for num_calls in [0, 10, 50, 100] {
+            let calls_left = Cell::new(usize::MAX);
+            let distance = |x: &[f64], y: &[f64]| {
+                calls_left.set(calls_left.get().saturating_sub(1));
+                if calls_left.get() == 0 {
+                    f64::NAN
+                } else {
+                    euclidean(x, y)
+                }
+            };
+            let rng = SmallRng::seed_from_u64(SEED);
+            let mut index = HNSW::new(8, 8, distance, rng).unwrap();
+
+            let ids = index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+            for &id in ids.iter().step_by(2) {
+                index.mark_deleted(id).unwrap();
+            }
+
+            calls_left.set(num_calls + 1);
+            assert_eq!(index.compact(false), Err(HnswError::InvalidDistance));
+            calls_left.set(usize::MAX);
+
+            // the vectors marked as deleted are still left out of the results
+            assert_eq!(index.stats().num_deleted, 50);
+            let results = index.search(&[10.2; 2], 10).unwrap();
+            assert_eq!(results.len(), 10);
+            assert!(results.iter().all(|r| r.id % 2 == 1));
+
+            // compacting again removes them without leaving an edge towards them
+            index.compact(false).unwrap();
+            assert_eq!(index.len(), 50);
+            for level in &index.levels {
+                assert!(level
+                    .iter()
+                    .all(|(id, mut edges)| id % 2 == 1 && edges.all(|id| id % 2 == 1)));
+            }
+            assert_eq!(index.search(&[10.2; 2], 10).unwrap().len(), 10);
+        }
+    }
+
+    #[test]
+    fn test_compact_renumber() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
+
+        let ids = index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+        for &id in &ids[..50] {
+            index.mark_deleted(id).unwrap();
+        }
+        let remapping = index.compact(true).unwrap();
+
+        // remaining ids are dense and keep their order
+        for (i, id) in ids[50..].iter().enumerate() {
+            assert_eq!(remapping[id], i);
+            assert_eq!(index.get(i), Some(&[(50 + i) as f64; 2]));
+        }
+        assert!(index.levels.iter().all(|level| {
+            level
+                .ids()
+                .chain(level.iter().flat_map(|(_, edges)| edges))
+                .all(|id| id < 50)
+        }));
+
+        assert_eq!(
+            index.search(&[60.2; 2], 1).unwrap()[0].id,
+            remapping[&ids[60]]
+        );
+        assert_eq!(index.insert(&[0.0; 2]).unwrap(), 50);
+    }
+
+    #[test]
+    fn test_compact_recall() {
+        let vectors = sample_clusters::<8>(1000, 10);
+        let queries = sample_clusters::<8>(50, 7);
+
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 64, euclidean, rng).unwrap();
+        let ids = index.insert_batch(vectors.iter().copied()).unwrap();
+
+        for &id in ids.iter().step_by(3) {
+            index.mark_deleted(id).unwrap();
+        }
+        let remapping = index.compact(true).unwrap();
+
+        let mut remaining = vec![[0.0; 8]; remapping.len()];
+        for (id, new_id) in remapping {
+            remaining[new_id] = vectors[id];
+        }
+
+        assert!(recall(&index, &remaining, &queries, 10) > 0.9);
+    }
+
+    #[test]
+    fn test_update() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(4, 16, euclidean, rng).unwrap();
+
+        let ids = index.insert_batch((0..100).map(|i| [i as f64; 2])).unwrap();
+        let levels_before = index
+            .levels
+            .iter()
+            .map(|level| level.contains(ids[10]))
+            .collect::<Vec<_>>();
+
+        index.update(ids[10], &[50.5; 2]).unwrap();
+
+        // same id and levels, new neighborhood
+        let levels_after = index
+            .levels
+            .iter()
+            .map(|level| level.contains(ids[10]))
+            .collect::<Vec<_>>();
+        assert_eq!(levels_before, levels_after);
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.get(ids[10]), Some(&[50.5; 2]));
+        assert_eq!(index.search(&[10.2; 2], 1).unwrap()[0].id, ids[11]);
+        assert_eq!(index.search(&[50.6; 2], 1).unwrap()[0].id, ids[10]);
+
+        let structure_ok = index.levels.iter().enumerate().all(|(level_index, level)| {
+            let max_connections = if level_index > 0 {
+                index.max_connections
+            } else {
+                index.max_connections_0
+            };
+            level.iter().all(|(_, edges)| {
+                let unique = edges.clone().collect::<HashSet<_>>();
+                edges.len() <= max_connections && unique.len() == edges.len()
+            })
+        });
+        assert!(structure_ok);
+    }
+
+    #[test]
+    fn test_update_invalid() {
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 8, cosine, rng).unwrap();
+
+        let id = index.insert(&[1., 2., 3.]).unwrap();
+
+        assert_eq!(
+            index.update(id + 1, &[1., 1., 1.]),
+            Err(HnswError::NodeNotFound(id + 1))
+        );
+        assert_eq!(
+            index.update(id, &[0., 0., 0.]),
+            Err(HnswError::InvalidDistance)
+        );
+        assert_eq!(index.get(id), Some(&[1., 2., 3.]));
+    }
+
+    #[test]
+    fn test_update_recall() {
+        let mut vectors = sample_clusters::<8>(1000, 10);
+        let queries = sample_clusters::<8>(50, 7);
+
+        let rng = SmallRng::seed_from_u64(SEED);
+        let mut index = HNSW::new(8, 64, euclidean, rng).unwrap();
+        let ids = index.insert_batch(vectors.iter().copied()).unwrap();
+
+        // move a third of the vectors onto their successor, in the next cluster, leaving exact duplicates
+        for (i, &id) in ids.iter().enumerate().step_by(3) {
+            vectors[i] = vectors[(i + 1) % vectors.len()];
+            index.update(id, &vectors[i]).unwrap();
+        }
+
+        assert!(recall(&index, &vectors, &queries, 10) > 0.9);
     }
 
     #[test]
     fn test_clear() {
         let rng = SmallRng::seed_from_u64(SEED);
-        let mut index = HNSW::new(8, 8, euclidean, rng);
+        let mut index = HNSW::new(8, 8, euclidean, rng).unwrap();
 
-        index.insert_batch((0..10).map(|i| [i as f64; 2]));
+        index.insert_batch((0..10).map(|i| [i as f64; 2])).unwrap();
 
         assert_eq!(index.len(), 10);
 
@@ -146,5 +1057,6 @@ mod tests {
 
         assert!(index.is_empty());
         assert_eq!(index.len(), 0);
+        assert!(index.entry_point.is_none());
     }
 }
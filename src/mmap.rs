@@ -0,0 +1,284 @@
+use crate::{
+    builder::Parameters,
+    error::HnswError,
+    format::{Element, IndexFile},
+    graph::{self, Graph},
+    hnsw::SearchResult,
+};
+use memmap2::Mmap;
+use std::{fmt::Debug, fs::File, marker::PhantomData, mem, path::Path};
+
+/// Read-only view over an index file written by `HNSW::save`, mapped in memory and searched in place. Vectors and edges
+/// are read from the file only when a search reaches them instead of being loaded upfront, and the pages of the file
+/// are shared through the page cache by every process mapping it. Nodes are designated by their rank in the file, and
+/// results carry the ids they had in the saved index
+pub struct MmapHnsw<T, const D: usize, F> {
+    file: IndexFile<Mmap>,
+    distance_metric: F,
+    num_deleted: usize,
+    ef_search: usize,
+    _element: PhantomData<T>,
+}
+
+impl<T, const D: usize, F> MmapHnsw<T, D, F>
+where
+    T: Element + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+{
+    /// Map an index file of vectors of the same type and dimension, using the given distance metric, which should be the
+    /// one the index was built with. Only the header of the file is checked, as verifying the checksum of the body would
+    /// read all of it: use `verify` to do it explicitly
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified, for instance by saving another index to the same path, or truncated while it is
+    /// mapped: the view would read inconsistent data or the process could be killed when reading a page that no longer
+    /// exists
+    pub unsafe fn open(path: impl AsRef<Path>, distance_metric: F) -> Result<Self, HnswError> {
+        let mmap = Mmap::map(&File::open(path)?)?;
+        let file = IndexFile::parse::<T>(mmap, D, false)?;
+
+        if file
+            .layout
+            .levels
+            .first()
+            .is_some_and(|level| level.num_members != file.header.num_nodes)
+        {
+            return Err(HnswError::InvalidFormat(
+                "nodes missing from the base level",
+            ));
+        }
+
+        // vectors are read in place, so they must be aligned in memory as their elements. The mapping starts on a page
+        // boundary and the vectors start on an offset multiple of 8 bytes, so this only fails on unusual platforms
+        let vectors = file.vector_bytes(0, 0).as_ptr();
+        if vectors.align_offset(mem::align_of::<T>()) != 0 {
+            return Err(HnswError::InvalidFormat(
+                "vectors are not aligned in memory",
+            ));
+        }
+
+        let num_deleted = (0..file.header.num_nodes)
+            .filter(|&rank| file.is_deleted(rank))
+            .count();
+        let ef_search = file.header.parameters.ef_search;
+
+        Ok(Self {
+            file,
+            distance_metric,
+            num_deleted,
+            ef_search,
+            _element: PhantomData,
+        })
+    }
+
+    /// Verify that the content of the file matches its checksum, which requires reading all of it
+    pub fn verify(&self) -> Result<(), HnswError> {
+        self.file.verify_body()
+    }
+
+    /// Return true if the index doesn't contain any vector, excluding the ones marked as deleted
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the number of vectors stored in the index, excluding the ones marked as deleted
+    pub fn len(&self) -> usize {
+        self.file.header.num_nodes - self.num_deleted
+    }
+
+    /// Return the vector stored with the given id, even if it is marked as deleted
+    pub fn get(&self, id: usize) -> Option<&[T; D]> {
+        let rank = self.file.rank(id).ok()??;
+        Some(self.vector_at(rank))
+    }
+
+    /// Return true if the vector stored with the given id is marked as deleted
+    pub fn is_deleted(&self, id: usize) -> bool {
+        matches!(self.file.rank(id), Ok(Some(rank)) if self.file.is_deleted(rank))
+    }
+
+    /// Return the vector of the node of a given rank, read in place from the mapped file
+    fn vector_at(&self, rank: usize) -> &[T; D] {
+        let bytes = self.file.vector_bytes(rank, mem::size_of::<[T; D]>());
+
+        // SAFETY: the slice holds exactly the bytes of `D` elements, aligned as checked in `open` since each vector
+        // starts on a multiple of its size. Elements are plain numbers for which any bytes are a valid value, stored
+        // in little endian like in memory on this platform
+        unsafe { &*bytes.as_ptr().cast::<[T; D]>() }
+    }
+
+    /// Search for the k nearest neighbors from the query vector by traveling the index
+    pub fn search(
+        &self,
+        query: &[T; D],
+        k: usize,
+    ) -> Result<Vec<SearchResult<'_, T, D>>, HnswError> {
+        self.search_with_ef(query, k, self.ef_search)
+    }
+
+    /// Search for the k nearest neighbors from the query vector, exploring the base level with a dynamic candidate list
+    /// of size `ef` instead of the one saved with the index, raised to `k` if smaller
+    pub fn search_with_ef(
+        &self,
+        query: &[T; D],
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<SearchResult<'_, T, D>>, HnswError> {
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        let entry_rank = self.file.header.entry_point.ok_or(HnswError::EmptyIndex)?;
+        let top_level_index = self.file.header.num_levels - 1;
+
+        graph::search(
+            self,
+            query,
+            entry_rank,
+            top_level_index,
+            ef.max(k),
+            |rank| !self.file.is_deleted(rank),
+        )?
+        .into_iter()
+        .take(k)
+        .map(|c| {
+            Ok(SearchResult::new(
+                self.file.id(c.id)?,
+                self.vector_at(c.id),
+                c.distance,
+            ))
+        })
+        .collect()
+    }
+}
+
+/// The graph is traveled in the rank space of the file, ranks being translated to ids only in the results
+impl<T, const D: usize, F> Graph<T> for MmapHnsw<T, D, F>
+where
+    T: Element + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+{
+    fn vector(&self, rank: usize) -> Result<&[T], HnswError> {
+        if rank < self.file.header.num_nodes {
+            Ok(self.vector_at(rank))
+        } else {
+            Err(HnswError::NodeNotFound(rank))
+        }
+    }
+
+    fn metric(&self, x: &[T], y: &[T]) -> f64 {
+        (self.distance_metric)(x, y)
+    }
+
+    fn for_each_neighbor(
+        &self,
+        level_index: usize,
+        rank: usize,
+        visit: impl FnMut(usize) -> Result<(), HnswError>,
+    ) -> Result<(), HnswError> {
+        match self.file.position(level_index, rank)? {
+            Some(position) => self.file.for_each_edge(level_index, position, visit),
+            None => Ok(()),
+        }
+    }
+
+    fn parameters(&self) -> Parameters {
+        self.file.header.parameters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MmapHnsw;
+    use crate::{distances::euclidean, error::HnswError, hnsw::HNSW};
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+    use std::{env, fs, path::PathBuf, process};
+
+    const SEED: u64 = 1234;
+
+    type Metric = fn(&[f32], &[f32]) -> f64;
+    type Index = HNSW<f32, 8, Metric, SmallRng>;
+
+    /// Path of a file in the temporary directory, unique to a test and to this process
+    fn temporary_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rust-hnsw-mmap-{}-{name}.bin", process::id()))
+    }
+
+    fn open(path: &PathBuf) -> Result<MmapHnsw<f32, 8, Metric>, HnswError> {
+        // SAFETY: the files of the tests are only written before being mapped
+        unsafe { MmapHnsw::open(path, euclidean) }
+    }
+
+    #[test]
+    fn test_search() {
+        let path = temporary_path("search");
+        let mut rng = SmallRng::seed_from_u64(SEED);
+        let mut index = Index::new(8, 32, euclidean, SmallRng::seed_from_u64(SEED)).unwrap();
+
+        let vectors = (0..500)
+            .map(|_| [(); 8].map(|_| rng.gen_range(-1.0..1.0)))
+            .collect::<Vec<[f32; 8]>>();
+        let ids = index.insert_batch(vectors.iter().copied()).unwrap();
+        index.remove(ids[3]).unwrap();
+        index.mark_deleted(ids[4]).unwrap();
+        index.save(&path).unwrap();
+
+        let mapped = open(&path).unwrap();
+        mapped.verify().unwrap();
+
+        assert_eq!(mapped.len(), index.len());
+        assert_eq!(mapped.get(ids[10]), index.get(ids[10]));
+        assert_eq!(mapped.get(ids[3]), None);
+        assert!(mapped.is_deleted(ids[4]));
+
+        // the same graph is traveled the same way
+        for query in vectors.iter().step_by(25) {
+            let found = mapped.search(query, 10).unwrap();
+            let expected = index.search(query, 10).unwrap();
+
+            assert_eq!(found.len(), expected.len());
+            for (f, e) in found.iter().zip(&expected) {
+                assert_eq!((f.id, f.vector, f.distance), (e.id, e.vector, e.distance));
+            }
+        }
+
+        drop(mapped);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_invalid() {
+        let path = temporary_path("invalid");
+        let mut index = Index::new(8, 16, euclidean, SmallRng::seed_from_u64(SEED)).unwrap();
+        index.save(&path).unwrap();
+
+        let mapped = open(&path).unwrap();
+        assert!(mapped.is_empty());
+        assert_eq!(
+            mapped.search(&[0.0; 8], 1).unwrap_err(),
+            HnswError::EmptyIndex
+        );
+        drop(mapped);
+
+        // the body is only verified on demand
+        index.insert_batch((0..10).map(|i| [i as f32; 8])).unwrap();
+        index.save(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        let end = bytes.len() - 1;
+        bytes[end] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(
+            open(&path).unwrap().verify(),
+            Err(HnswError::ChecksumMismatch)
+        );
+
+        fs::write(&path, b"not an index").unwrap();
+        assert!(matches!(
+            open(&path).err(),
+            Some(HnswError::InvalidFormat(_))
+        ));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
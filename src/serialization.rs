@@ -0,0 +1,436 @@
+//! Serialization of the index with serde, enabled by the `serde` feature.
+//!
+//! An index is serialized with its parameters, vectors and levels, sorted by id so that the same index always gives
+//! the same output. The distance metric and the random number generator cannot be serialized, so they are given back
+//! when deserializing, either with `HNSW::deserialize_with` or with an `HnswSeed` when the index is nested in another
+//! value deserialized with `DeserializeSeed`.
+
+use crate::{
+    builder::Parameters,
+    hnsw::{SearchResult, HNSW},
+    level,
+};
+use rand::Rng;
+use serde::{
+    de::{self, DeserializeSeed, SeqAccess, Visitor},
+    ser::{SerializeStruct, SerializeTuple},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::{self, Debug},
+    marker::PhantomData,
+};
+
+/// Vector serialized as a tuple of `D` elements like arrays, for which serde only implements the traits up to 32
+/// elements
+struct VectorRef<'v, T, const D: usize>(&'v [T; D]);
+
+impl<T: Serialize, const D: usize> Serialize for VectorRef<'_, T, D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(D)?;
+        for element in self.0 {
+            tuple.serialize_element(element)?;
+        }
+        tuple.end()
+    }
+}
+
+struct Vector<T, const D: usize>([T; D]);
+
+impl<'de, T: Deserialize<'de>, const D: usize> Deserialize<'de> for Vector<T, D> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        struct VectorVisitor<T, const D: usize>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>, const D: usize> Visitor<'de> for VectorVisitor<T, D> {
+            type Value = Vector<T, D>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "a vector of {D} elements")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut elements = Vec::with_capacity(D);
+                while let Some(element) = seq.next_element()? {
+                    elements.push(element);
+                }
+
+                let length = elements.len();
+                elements
+                    .try_into()
+                    .map(Vector)
+                    .map_err(|_| de::Error::invalid_length(length, &self))
+            }
+        }
+
+        deserializer.deserialize_tuple(D, VectorVisitor(PhantomData))
+    }
+}
+
+impl<T: Serialize, const D: usize> Serialize for SearchResult<'_, T, D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut result = serializer.serialize_struct("SearchResult", 3)?;
+        result.serialize_field("id", &self.id)?;
+        result.serialize_field("vector", &VectorRef(self.vector))?;
+        result.serialize_field("distance", &self.distance)?;
+        result.end()
+    }
+}
+
+/// Content of an index being serialized, borrowed from it
+#[derive(Serialize)]
+#[serde(rename = "HNSW", bound = "T: Serialize")]
+struct IndexRef<'i, T, const D: usize> {
+    parameters: Parameters,
+    nodes: BTreeMap<usize, VectorRef<'i, T, D>>,
+    levels: Vec<BTreeMap<usize, Vec<usize>>>,
+    entry_point: Option<usize>,
+    deleted: BTreeSet<usize>,
+    next_id: usize,
+}
+
+/// Largest ratio between the ids of an index and its number of nodes, as the vectors and the base level are stored by
+/// id once deserialized. It bounds the memory allocated by a multiple of the size of the input
+const MAX_ID_SPARSITY: usize = 16;
+
+/// Return the bound below which the ids of an index of `num_nodes` nodes must be, leaving room for a few large ids in a
+/// small index
+fn id_bound(num_nodes: usize) -> usize {
+    num_nodes.saturating_add(64).saturating_mul(MAX_ID_SPARSITY)
+}
+
+/// Content of an index being deserialized, before it is checked
+#[derive(Deserialize)]
+#[serde(rename = "HNSW", bound = "T: Deserialize<'de>")]
+struct IndexData<T, const D: usize> {
+    parameters: Parameters,
+    nodes: HashMap<usize, Vector<T, D>>,
+    levels: Vec<HashMap<usize, Vec<usize>>>,
+    entry_point: Option<usize>,
+    deleted: HashSet<usize>,
+    next_id: usize,
+}
+
+impl<T, const D: usize> IndexData<T, D> {
+    /// Check that the ids can be stored, and that the levels only connect stored nodes within the maximum number of
+    /// connections and can be traveled from the entry point. The vectors and the base level being stored by id, ids
+    /// more than 16 times the number of nodes are rejected before anything is allocated for them
+    fn check(&self, parameters: &Parameters) -> Result<(), &'static str> {
+        let id_bound = id_bound(self.nodes.len());
+        for &id in self.nodes.keys() {
+            if id >= self.next_id {
+                return Err("id larger than the next id");
+            }
+            if id >= id_bound {
+                return Err("ids too sparse for the number of nodes");
+            }
+            if level::check_id(id, parameters.compact_ids).is_err() {
+                return Err("id too large to be stored in the levels");
+            }
+        }
+        if self.deleted.iter().any(|id| !self.nodes.contains_key(id)) {
+            return Err("unknown deleted node");
+        }
+        let base_level = self.levels.first();
+        if base_level.map_or(0, HashMap::len) != self.nodes.len()
+            || base_level.is_some_and(|level| level.keys().any(|id| !self.nodes.contains_key(id)))
+        {
+            return Err("base level not made of the nodes");
+        }
+
+        for (level_index, level) in self.levels.iter().enumerate() {
+            let lower_level = level_index.checked_sub(1).map(|lower| &self.levels[lower]);
+            let max_connections = if level_index > 0 {
+                parameters.max_connections
+            } else {
+                parameters.max_connections_0
+            };
+
+            for (id, edges) in level {
+                if std::iter::once(id)
+                    .chain(edges)
+                    .any(|&id| level::check_id(id, parameters.compact_ids).is_err())
+                {
+                    return Err("id too large to be stored in the levels");
+                }
+                if edges.len() > max_connections {
+                    return Err("more edges than the maximum number of connections");
+                }
+                if lower_level.is_some_and(|lower_level| !lower_level.contains_key(id)) {
+                    return Err("node missing from a lower level");
+                }
+                if edges
+                    .iter()
+                    .any(|neighbor_id| !level.contains_key(neighbor_id))
+                {
+                    return Err("edge towards a node outside of the level");
+                }
+            }
+        }
+
+        match (self.entry_point, self.levels.last()) {
+            (None, None) => Ok(()),
+            (Some(entry_id), Some(top_level)) if top_level.contains_key(&entry_id) => Ok(()),
+            _ => Err("entry point outside of the top level"),
+        }
+    }
+}
+
+impl<T, const D: usize, F, R> Serialize for HNSW<T, D, F, R>
+where
+    T: Serialize + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        IndexRef {
+            parameters: self.parameters(),
+            nodes: self
+                .nodes
+                .iter()
+                .map(|(id, vector)| (id, VectorRef(vector)))
+                .collect(),
+            levels: self
+                .levels
+                .iter()
+                .map(|level| {
+                    level
+                        .iter()
+                        .map(|(id, edges)| (id, edges.collect()))
+                        .collect()
+                })
+                .collect(),
+            entry_point: self.entry_point,
+            deleted: self.deleted.iter().copied().collect(),
+            next_id: self.next_id,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Distance metric and random number generator of an index being deserialized, to deserialize an index nested in
+/// another value with `DeserializeSeed`
+pub struct HnswSeed<T, const D: usize, F, R> {
+    distance_metric: F,
+    rng: R,
+    element: PhantomData<T>,
+}
+
+impl<T, const D: usize, F, R> HnswSeed<T, D, F, R> {
+    pub fn new(distance_metric: F, rng: R) -> Self {
+        Self {
+            distance_metric,
+            rng,
+            element: PhantomData,
+        }
+    }
+}
+
+impl<'de, T, const D: usize, F, R> DeserializeSeed<'de> for HnswSeed<T, D, F, R>
+where
+    T: Deserialize<'de> + Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    type Value = HNSW<T, D, F, R>;
+
+    fn deserialize<De: Deserializer<'de>>(
+        self,
+        deserializer: De,
+    ) -> Result<Self::Value, De::Error> {
+        let data = IndexData::<T, D>::deserialize(deserializer)?;
+        let parameters = data.parameters.validate().map_err(de::Error::custom)?;
+        data.check(&parameters)
+            .map_err(|reason| de::Error::custom(format!("invalid index: {reason}")))?;
+
+        let mut index = HNSW::from_parameters(parameters, self.distance_metric, self.rng);
+        index.nodes = data
+            .nodes
+            .into_iter()
+            .map(|(id, vector)| (id, vector.0))
+            .collect();
+        index.levels = data
+            .levels
+            .into_iter()
+            .enumerate()
+            .map(|(level_index, edges_by_id)| {
+                let mut level = index.empty_level(level_index);
+                for (id, edges) in edges_by_id {
+                    level.insert(id);
+                    level.set(id, edges);
+                }
+
+                level
+            })
+            .collect();
+        index.entry_point = data.entry_point;
+        index.deleted = data.deleted;
+        index.next_id = data.next_id;
+
+        Ok(index)
+    }
+}
+
+impl<T, const D: usize, F, R> HNSW<T, D, F, R>
+where
+    T: Copy + Debug,
+    F: Fn(&[T], &[T]) -> f64,
+    R: Rng,
+{
+    /// Deserialize an index serialized with serde, using the given distance metric, which should be the one the index
+    /// was built with, and random number generator. Invalid parameters or levels referring to unknown nodes are
+    /// rejected
+    pub fn deserialize_with<'de, De>(
+        deserializer: De,
+        distance_metric: F,
+        rng: R,
+    ) -> Result<Self, De::Error>
+    where
+        T: Deserialize<'de>,
+        De: Deserializer<'de>,
+    {
+        HnswSeed::new(distance_metric, rng).deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        builder::HnswBuilder,
+        distances::euclidean,
+        hnsw::{NeighborSelection, HNSW},
+    };
+    use rand::{rngs::SmallRng, SeedableRng};
+    use serde_json::{json, Value};
+
+    const SEED: u64 = 1234;
+
+    type Index = HNSW<f32, 2, fn(&[f32], &[f32]) -> f64, SmallRng>;
+
+    fn deserialize(value: &Value) -> Result<Index, serde_json::Error> {
+        Index::deserialize_with(value, euclidean, SmallRng::seed_from_u64(SEED))
+    }
+
+    #[test]
+    fn test_serialize_index() {
+        let mut index = Index::new(8, 16, euclidean, SmallRng::seed_from_u64(SEED)).unwrap();
+        let ids = index.insert_batch((0..100).map(|i| [i as f32; 2])).unwrap();
+        index.remove(ids[5]).unwrap();
+        index.mark_deleted(ids[6]).unwrap();
+
+        let value = serde_json::to_value(&index).unwrap();
+        assert_eq!(value["parameters"]["connections"], 8);
+        assert_eq!(value["nodes"]["7"], json!([7.0, 7.0]));
+
+        let deserialized = deserialize(&value).unwrap();
+        assert_eq!(deserialized.nodes, index.nodes);
+        assert_eq!(deserialized.levels, index.levels);
+        assert_eq!(deserialized.entry_point, index.entry_point);
+        assert_eq!(deserialized.deleted, index.deleted);
+        assert_eq!(deserialized.next_id, index.next_id);
+
+        // the output doesn't depend on the order of the hash maps
+        assert_eq!(
+            serde_json::to_string(&deserialized).unwrap(),
+            serde_json::to_string(&index).unwrap()
+        );
+
+        let found = deserialized.search(&[6.2; 2], 3).unwrap();
+        assert_eq!(serde_json::to_value(&found[0]).unwrap()["id"], ids[7]);
+        assert_eq!(
+            serde_json::to_value(&found[0]).unwrap(),
+            serde_json::to_value(&index.search(&[6.2; 2], 3).unwrap()[0]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        let mut index = Index::new(8, 16, euclidean, SmallRng::seed_from_u64(SEED)).unwrap();
+        index.insert_batch((0..10).map(|i| [i as f32; 2])).unwrap();
+        let value = serde_json::to_value(&index).unwrap();
+
+        let mut invalid = value.clone();
+        invalid["parameters"]["connections"] = json!(0);
+        assert!(deserialize(&invalid).is_err());
+
+        let mut invalid = value.clone();
+        invalid["levels"][0]["3"] = json!([42]);
+        assert!(deserialize(&invalid).is_err());
+
+        let mut invalid = value.clone();
+        invalid["levels"][0]["3"] = json!(vec![0; 17]);
+        assert!(deserialize(&invalid).is_err());
+
+        let mut invalid = value.clone();
+        invalid["nodes"]["3"] = json!([1.0, 2.0, 3.0]);
+        assert!(deserialize(&invalid).is_err());
+
+        let mut invalid = value;
+        invalid["entry_point"] = json!(null);
+        assert!(deserialize(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_invalid_ids() {
+        let mut index: Index = HnswBuilder::new(8)
+            .compact_ids(true)
+            .build_with_rng(
+                euclidean as fn(&[f32], &[f32]) -> f64,
+                SmallRng::seed_from_u64(SEED),
+            )
+            .unwrap();
+        index.insert_batch((0..10).map(|i| [i as f32; 2])).unwrap();
+        let value = serde_json::to_value(&index).unwrap();
+        let reason = |value: &Value| deserialize(value).map(drop).unwrap_err().to_string();
+
+        // ids sparser than the number of nodes allows are rejected before storing the vectors by id
+        let mut invalid = value.clone();
+        let vector = invalid["nodes"]
+            .as_object_mut()
+            .unwrap()
+            .remove("3")
+            .unwrap();
+        invalid["nodes"]["100000"] = vector;
+        invalid["next_id"] = json!(100001);
+        assert!(reason(&invalid).contains("ids too sparse"));
+
+        // edges and members of the levels too large to be stored on 32 bits
+        let too_large = (u32::MAX as usize).to_string();
+        let mut invalid = value.clone();
+        invalid["levels"][0]["3"] = json!([u32::MAX]);
+        assert!(reason(&invalid).contains("id too large"));
+
+        let mut invalid = value.clone();
+        invalid["levels"].as_array_mut().unwrap().push(json!({}));
+        let top_level = invalid["levels"].as_array().unwrap().len() - 1;
+        invalid["levels"][top_level][&too_large] = json!([]);
+        assert!(reason(&invalid).contains("id too large"));
+
+        // a base level of the same size as the nodes but with other ids
+        let mut invalid = value;
+        let edges = invalid["levels"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("3")
+            .unwrap();
+        invalid["levels"][0]["42"] = edges;
+        assert!(reason(&invalid).contains("base level not made of the nodes"));
+    }
+
+    #[test]
+    fn test_builder() {
+        let builder = HnswBuilder::new(12)
+            .ef_search(50)
+            .pruning(NeighborSelection::Simple);
+        let value = serde_json::to_value(&builder).unwrap();
+        assert_eq!(value["connections"], 12);
+        assert_eq!(value["pruning"], "Simple");
+
+        // parameters left out of a configuration take their default value
+        let builder: HnswBuilder = serde_json::from_value(json!({ "connections": 4 })).unwrap();
+        let parameters = builder.parameters().unwrap();
+        assert_eq!(parameters.connections, 4);
+        assert_eq!(parameters.max_connections_0, 8);
+        assert_eq!(parameters.ef_construction, 100);
+    }
+}